@@ -1,13 +1,37 @@
 import socket
 import json
+import struct
 import time
 import random
 
+
+# Frames are a 4-byte big-endian length followed by the JSON payload,
+# matching src/codec.rs on the Rust side.
+def send_frame(sock, payload):
+    sock.sendall(struct.pack('>I', len(payload)) + payload)
+
+
+def recv_exact(sock, n):
+    data = b''
+    while len(data) < n:
+        chunk = sock.recv(n - len(data))
+        if not chunk:
+            raise ConnectionError('connection closed mid-frame')
+        data += chunk
+    return data
+
+
+def recv_frame(sock):
+    (length,) = struct.unpack('>I', recv_exact(sock, 4))
+    return recv_exact(sock, length)
+
+
 class EClient:
-    def __init__(self, e_router_port, workload, hop):
+    def __init__(self, e_router_port, workload, hop, function="default"):
         self.e_router_port = e_router_port
         self.workload = workload
         self.hop = hop
+        self.function = function
 
     def simulate_network_delay(self):
         # Simulate a random network delay between 0.1 and 0.5 seconds
@@ -18,12 +42,12 @@ class EClient:
         # Create a task
         task = {
             "id": f"task-{random.randint(1, 1000)}",
-            "size": self.workload
+            "size": self.workload,
+            "function": self.function
         }
 
         # Record start time
         start_time = time.time()
-        print(start_time)
 
         # Simulate network delay
         self.simulate_network_delay()
@@ -31,10 +55,10 @@ class EClient:
         # Connect to e-router
         with socket.socket(socket.AF_INET, socket.SOCK_STREAM) as s:
             s.connect(('localhost', self.e_router_port))
-            s.sendall(json.dumps(task).encode('utf-8'))
+            send_frame(s, json.dumps(task).encode('utf-8'))
 
             # Wait for response
-            response = s.recv(1024)
+            response = recv_frame(s)
             end_time = time.time()
 
         # Calculate process time
@@ -0,0 +1,80 @@
+use acn_final_rs::cli::Args;
+use acn_final_rs::loadgen::{self, Arrivals, LoadConfig};
+use acn_final_rs::protocol::DEFAULT_FUNCTION;
+use acn_final_rs::workload::SizeDistribution;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::from_env();
+    if args.positional.len() != 1 {
+        eprintln!(
+            "Usage: {} <router-port> [--mode closed|open] [--clients N] [--tasks M] [--rate R] [--size SPEC] [--function NAME] [--seed N] [--timeout-ms N] [--output FILE]",
+            args.program
+        );
+        eprintln!("  closed: N clients each send M tasks back to back (default 1 x 100)");
+        eprintln!("  open:   M tasks arrive as a Poisson process at R tasks per second");
+        eprintln!("  SPEC:   SIZE, fixed:SIZE, uniform:MIN:MAX or exp:MEAN (default 5000)");
+        std::process::exit(1);
+    }
+
+    let router_port: u16 = args.positional[0]
+        .parse()
+        .expect("Router port must be a port number");
+    let tasks: usize = args.get_or("tasks", 100);
+    let (arrivals, default_output) = match args.raw("mode").unwrap_or("closed") {
+        "closed" => {
+            let clients: usize = args.get_or("clients", 1);
+            let arrivals = Arrivals::Closed {
+                clients,
+                tasks_per_client: tasks,
+            };
+            (arrivals, format!("process-times-{}.log", clients))
+        }
+        "open" => {
+            let rate: f64 = args.get_or("rate", 10.0);
+            if !(rate > 0.0 && rate.is_finite()) {
+                eprintln!("Arrival rate must be a positive number");
+                std::process::exit(1);
+            }
+            let arrivals = Arrivals::Poisson { rate, tasks };
+            (arrivals, format!("process-times-poisson-{}.log", rate))
+        }
+        mode => {
+            eprintln!("Invalid mode {:?}. Choose closed or open.", mode);
+            std::process::exit(1);
+        }
+    };
+
+    let config = LoadConfig {
+        router_port,
+        arrivals,
+        sizes: args.get_or("size", SizeDistribution::Fixed(5000)),
+        function: args.get_or("function", DEFAULT_FUNCTION.to_string()),
+        seed: args.get("seed"),
+        timeout: args.get("timeout-ms").map(Duration::from_millis),
+    };
+    // Progress goes to stderr so `--output -` leaves only latencies on stdout
+    eprintln!(
+        "Sending {:?} with task size {} to port {}",
+        config.arrivals, config.sizes, router_port
+    );
+
+    let outcome = loadgen::run(config).await;
+
+    let output = args.get_or("output", default_output);
+    loadgen::write_log(&output, &outcome.latencies)?;
+    eprintln!(
+        "{} tasks completed, {} failed in {:.2?} ({:.1} tasks/s). Latencies written to {}",
+        outcome.latencies.len(),
+        outcome.failures,
+        outcome.elapsed,
+        outcome.latencies.len() as f64 / outcome.elapsed.as_secs_f64(),
+        output
+    );
+    for (kind, count) in &outcome.failure_kinds {
+        eprintln!("  {}: {}", kind, count);
+    }
+
+    Ok(())
+}
@@ -1,48 +1,159 @@
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::io::{Read, Write};
+use acn_final_rs::cli::Args;
+use acn_final_rs::codec;
+use acn_final_rs::coldstart::{ColdStartConfig, InstancePool, DEFAULT_KEEP_ALIVE};
+use acn_final_rs::functions::Hosts;
+use acn_final_rs::protocol::{Feedback, Registration, Response, Status, Task};
+use acn_final_rs::registration;
+use acn_final_rs::service::{ServiceModel, ServiceTime};
+use acn_final_rs::work::{WorkMode, Worker, DEFAULT_CALIBRATION_WINDOW};
 use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Task {
-    id: String,
-    size: u64,
+const DEFAULT_WORKERS: usize = 1;
+const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+// A worker gives up on sending a response after this long rather than hang
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What the worker slots share: how fast this device is and how it spends a task
+struct Device {
+    speed: u64,
+    service: Mutex<ServiceModel>,
+    worker: Worker,
+    instances: Mutex<InstancePool>,
+    started: Instant,
+    workers: usize,
+    // Tasks in the run queue and tasks running, reported back with every response
+    queued: AtomicUsize,
+    active: AtomicUsize,
+}
+
+impl Device {
+    fn feedback(&self, service: Option<Duration>, queue: Option<Duration>) -> Feedback {
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        Feedback {
+            queue_len: self.queued.load(Ordering::Relaxed),
+            active_workers: self.active.load(Ordering::Relaxed),
+            workers: self.workers,
+            service_ms: service.map(millis),
+            queue_ms: queue.map(millis),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Response {
-    id: String,
-    status: String,
+// A task waiting to be processed, with the connection its response goes back on
+struct Job {
+    request_id: u64,
+    task: Task,
+    reply_to: Arc<Mutex<TcpStream>>,
+    enqueued: Instant,
 }
 
 fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <speed>", args[0]);
+    let args = Args::from_env();
+    if args.positional.len() != 1 {
+        eprintln!(
+            "Usage: {} <speed> [--workers N] [--queue N] [--service det|exp|lognormal:SIGMA|bimodal:P:FACTOR] [--seed N] [--work sleep|cpu] [--ops-per-sec N] [--cold-start-ms N] [--keep-alive-ms N] [--max-instances N] [--register CONTROL_PORT] [--hops N] [--functions f1,f2]",
+            args.program
+        );
+        eprintln!("  --register joins the e-router with that control port, announcing --hops (default 0) and --functions (default all)");
         std::process::exit(1);
     }
 
-    let speed: u64 = args[1].parse().expect("Speed must be a positive integer");
+    let speed: u64 = args.positional[0]
+        .parse()
+        .expect("Speed must be a positive integer");
+    let workers: usize = args.get_or("workers", DEFAULT_WORKERS).max(1);
+    let queue_capacity: usize = args.get_or("queue", DEFAULT_QUEUE_CAPACITY);
+    // A zero-capacity channel only hands a task over to a worker already
+    // waiting in `recv`, so tasks would be rejected with workers idle
+    if queue_capacity == 0 {
+        eprintln!("Run queue capacity must be at least 1");
+        std::process::exit(1);
+    }
+    let service = ServiceModel::new(
+        args.get_or("service", ServiceTime::Deterministic),
+        args.get("seed"),
+    );
+    let worker = match args.get_or("work", WorkMode::Sleep) {
+        WorkMode::Sleep => Worker::sleeping(),
+        WorkMode::Cpu => match args.get("ops-per-sec") {
+            Some(ops_per_sec) => Worker::cpu(ops_per_sec),
+            None => Worker::calibrate(DEFAULT_CALIBRATION_WINDOW),
+        },
+    };
+
+    let cold_start = ColdStartConfig {
+        penalty: Duration::from_millis(args.get_or("cold-start-ms", 0)),
+        keep_alive: args
+            .get("keep-alive-ms")
+            .map_or(DEFAULT_KEEP_ALIVE, Duration::from_millis),
+        max_instances: args.get("max-instances"),
+    };
 
     let listener = TcpListener::bind("127.0.0.1:0")?;
     let port = listener.local_addr()?.port();
     println!("E-computer simulator listening on port {}", port);
+    println!(
+        "{} worker(s), run queue capacity {}, service time {}, work mode {}",
+        workers,
+        queue_capacity,
+        service.kind(),
+        worker.mode()
+    );
+    if worker.mode() == WorkMode::Cpu {
+        println!("Calibrated at {:.0} ops/sec", worker.ops_per_sec());
+    }
+    println!(
+        "Cold start penalty {:?}, keep-alive {:?}",
+        cold_start.penalty, cold_start.keep_alive
+    );
+    let device = Arc::new(Device {
+        speed,
+        service: Mutex::new(service),
+        worker,
+        instances: Mutex::new(InstancePool::new(cold_start)),
+        started: Instant::now(),
+        workers,
+        queued: AtomicUsize::new(0),
+        active: AtomicUsize::new(0),
+    });
 
-    // Set non-blocking mode for the listener
-    listener.set_nonblocking(true)?;
+    // Tasks wait in a bounded FIFO run queue until one of the worker slots is
+    // free. When the queue is full new tasks are rejected straight away.
+    let (jobs, queue) = mpsc::sync_channel(queue_capacity);
+    let queue = Arc::new(Mutex::new(queue));
+    for _ in 0..workers {
+        let queue = Arc::clone(&queue);
+        let device = Arc::clone(&device);
+        thread::spawn(move || process_tasks(queue, device));
+    }
+
+    // Announce ourselves to the e-router and keep the lease alive
+    if let Some(control_port) = args.get::<u16>("register") {
+        let hosts: Hosts = args.get_or("functions", Hosts::All);
+        let registration = Registration {
+            port,
+            hops: args.get_or("hops", 0),
+            capacity: workers,
+            functions: hosts.to_string(),
+        };
+        thread::spawn(move || registration::keep_registered(control_port, registration));
+        registration::deregister_on_shutdown(control_port, port);
+    }
 
-    // Process tasks sequentially
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                handle_client(stream, speed).unwrap_or_else(|error| eprintln!("Error: {}", error));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No incoming connection, sleep a bit to prevent busy-waiting
-                thread::sleep(Duration::from_millis(100));
-                continue;
+                let jobs = jobs.clone();
+                let device = Arc::clone(&device);
+                thread::spawn(move || {
+                    handle_connection(stream, jobs, &device)
+                        .unwrap_or_else(|error| eprintln!("Error: {}", error));
+                });
             }
             Err(e) => {
                 eprintln!("Error: {}", e);
@@ -53,23 +164,123 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
-fn handle_client(mut stream: TcpStream, speed: u64) -> std::io::Result<()> {
-    let mut buffer = [0; 1024];
-    let bytes_read = stream.read(&mut buffer)?;
+// Read tasks off one connection until the e-router closes it
+fn handle_connection(
+    mut stream: TcpStream,
+    jobs: SyncSender<Job>,
+    device: &Device,
+) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    let reply_to = stream.try_clone()?;
+    reply_to.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let reply_to = Arc::new(Mutex::new(reply_to));
 
-    let task: Task = serde_json::from_slice(&buffer[..bytes_read])?;
-    println!("Received task: {:?}", task);
+    while let Some((request_id, payload)) = codec::try_read_tagged_frame(&mut stream)? {
+        let task: Task = match serde_json::from_slice(&payload) {
+            Ok(task) => task,
+            Err(error) => {
+                println!("Bad request: {}", error);
+                let response = Response::error("", Status::BadRequest, error.to_string());
+                send_response(&reply_to, request_id, &response)?;
+                continue;
+            }
+        };
+        println!("Received task: {:?}", task);
+        let job = Job {
+            request_id,
+            task,
+            reply_to: Arc::clone(&reply_to),
+            enqueued: Instant::now(),
+        };
+        // Counted before it is sent so a worker never takes it off first
+        device.queued.fetch_add(1, Ordering::Relaxed);
+        match jobs.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(job)) => {
+                device.queued.fetch_sub(1, Ordering::Relaxed);
+                println!("Run queue full, rejecting task {}", job.task.id);
+                respond(
+                    &job,
+                    Response::error(&job.task.id, Status::Overloaded, "Run queue full")
+                        .with_feedback(device.feedback(None, None)),
+                )?;
+            }
+            Err(TrySendError::Disconnected(_)) => break,
+        }
+    }
 
-    let processing_time = Duration::from_secs(task.size / speed);
-    thread::sleep(processing_time);
+    Ok(())
+}
 
-    let response = Response {
-        id: task.id,
-        status: "success".to_string(),
-    };
+fn process_tasks(queue: Arc<Mutex<Receiver<Job>>>, device: Arc<Device>) {
+    loop {
+        // Only hold the lock while taking the next job, not while running it
+        let job = match queue.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        device.queued.fetch_sub(1, Ordering::Relaxed);
+        handle_job(job, &device).unwrap_or_else(|error| eprintln!("Error: {}", error));
+    }
+}
 
-    let response_json = serde_json::to_string(&response)?;
-    stream.write_all(response_json.as_bytes())?;
+fn handle_job(job: Job, device: &Device) -> std::io::Result<()> {
+    let queue_time = job.enqueued.elapsed();
+    // Nobody is waiting for a task whose deadline passed while it was queued
+    if job.task.is_expired() {
+        println!("Task {} expired in the run queue", job.task.id);
+        return respond(
+            &job,
+            Response::error(
+                &job.task.id,
+                Status::Expired,
+                "Deadline passed in the run queue",
+            )
+            .with_feedback(device.feedback(None, Some(queue_time))),
+        );
+    }
+    device.active.fetch_add(1, Ordering::Relaxed);
+    let service_start = Instant::now();
 
-    Ok(())
+    let processing_time = device
+        .service
+        .lock()
+        .unwrap()
+        .sample(job.task.size, device.speed);
+    let function = &job.task.function;
+
+    let (start_up, cold) = device
+        .instances
+        .lock()
+        .unwrap()
+        .acquire(function, device.started.elapsed());
+    if cold {
+        println!("Cold start of {} for task {}", function, job.task.id);
+    }
+    device.worker.run(start_up + processing_time);
+    device
+        .instances
+        .lock()
+        .unwrap()
+        .release(function, device.started.elapsed());
+    device.active.fetch_sub(1, Ordering::Relaxed);
+
+    let feedback = device.feedback(Some(service_start.elapsed()), Some(queue_time));
+    respond(
+        &job,
+        Response::ok(&job.task.id, cold).with_feedback(feedback),
+    )
+}
+
+fn respond(job: &Job, response: Response) -> std::io::Result<()> {
+    send_response(&job.reply_to, job.request_id, &response)
+}
+
+fn send_response(
+    reply_to: &Mutex<TcpStream>,
+    request_id: u64,
+    response: &Response,
+) -> std::io::Result<()> {
+    let response_json = serde_json::to_vec(response)?;
+    codec::write_tagged_frame(&mut *reply_to.lock().unwrap(), request_id, &response_json)
 }
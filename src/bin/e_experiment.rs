@@ -0,0 +1,13 @@
+use acn_final_rs::cli::Args;
+use acn_final_rs::experiment::{self, Scenario};
+
+fn main() -> std::io::Result<()> {
+    let args = Args::from_env();
+    if args.positional.len() != 1 {
+        eprintln!("Usage: {} <scenario.toml|scenario.json>", args.program);
+        std::process::exit(1);
+    }
+
+    let scenario = Scenario::load(&args.positional[0])?;
+    experiment::run(&scenario)
+}
@@ -0,0 +1,93 @@
+use acn_final_rs::cli::Args;
+use acn_final_rs::codec;
+use acn_final_rs::protocol::{Control, ControlReply, Inspection};
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+fn main() -> io::Result<()> {
+    let args = Args::from_env();
+    if args.positional.is_empty() {
+        eprintln!(
+            "Usage: {} <router-control-port> [--interval-ms N] [--format text|json]",
+            args.program
+        );
+        eprintln!(
+            "  Shows the tasks the e-router has in flight and the latency objectives (--slo)"
+        );
+        eprintln!("  it counted them against; --interval-ms repeats every N ms");
+        std::process::exit(1);
+    }
+
+    let control_port: u16 = args.positional[0].parse().unwrap_or_else(|_| {
+        eprintln!("Invalid port {:?}", args.positional[0]);
+        std::process::exit(1);
+    });
+    let interval: Option<u64> = args.get("interval-ms");
+    let json = match args.raw("format").unwrap_or("text") {
+        "text" => false,
+        "json" => true,
+        format => {
+            eprintln!("Invalid format {:?}. Choose text or json.", format);
+            std::process::exit(1);
+        }
+    };
+
+    let mut stream = TcpStream::connect(("127.0.0.1", control_port))?;
+    stream.set_nodelay(true)?;
+    loop {
+        codec::send(&mut stream, &Control::Inspect)?;
+        let inspection = match codec::recv(&mut stream)? {
+            ControlReply::Inspection(inspection) => inspection,
+            reply => {
+                return Err(io::Error::other(format!(
+                    "Unexpected reply to inspect: {:?}",
+                    reply
+                )));
+            }
+        };
+        if json {
+            println!("{}", serde_json::to_string(&inspection)?);
+        } else {
+            print_text(&inspection);
+        }
+        match interval {
+            Some(interval) => thread::sleep(Duration::from_millis(interval)),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn print_text(inspection: &Inspection) {
+    println!("{:>6} {:>9}", "port", "in flight");
+    for computer in &inspection.computers {
+        println!("{:>6} {:>9}", computer.port, computer.in_flight);
+    }
+    if !inspection.requests.is_empty() {
+        println!("{:>6} {:>9}  {:<16} task", "port", "age ms", "function");
+        for request in &inspection.requests {
+            println!(
+                "{:>6} {:>9}  {:<16} {}",
+                request.port, request.age_ms, request.function, request.task
+            );
+        }
+    }
+    if !inspection.slos.is_empty() {
+        println!(
+            "{:<16} {:>12} {:>8} {:>10}  status",
+            "function", "objective", "tasks", "violations"
+        );
+        for slo in &inspection.slos {
+            println!(
+                "{:<16} {:>12} {:>8} {:>10}  {}",
+                slo.function,
+                format!("p{}<{}ms", slo.percentile, slo.target_ms),
+                slo.tasks,
+                slo.violations,
+                if slo.met { "met" } else { "missed" }
+            );
+        }
+    }
+    println!();
+}
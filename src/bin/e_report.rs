@@ -0,0 +1,61 @@
+use acn_final_rs::cli::Args;
+use acn_final_rs::stats::{self, Summary, CSV_HEADER, DEFAULT_RESAMPLES};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+fn main() -> io::Result<()> {
+    let args = Args::from_env();
+    if args.positional.is_empty() {
+        eprintln!(
+            "Usage: {} <latency-log|-> [<latency-log> ...] [--format text|json|csv] [--resamples N] [--seed N]",
+            args.program
+        );
+        eprintln!("  `-` reads live client output from stdin");
+        std::process::exit(1);
+    }
+
+    let resamples: usize = args.get_or("resamples", DEFAULT_RESAMPLES);
+    let seed: u64 = args.get_or("seed", 0);
+
+    let mut summaries: Vec<Summary> = Vec::new();
+    for source in &args.positional {
+        let (name, text) = if source == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            ("stdin".to_string(), text)
+        } else {
+            // Name each run after its file, e.g. `process-times-rp-4`
+            let name = Path::new(source)
+                .file_stem()
+                .map_or(source.clone(), |stem| stem.to_string_lossy().to_string());
+            (name, fs::read_to_string(source)?)
+        };
+        let latencies = stats::parse_latencies(&text);
+        match stats::summarize(&name, &latencies, resamples, seed) {
+            Some(summary) => summaries.push(summary),
+            None => eprintln!("No latencies found in {}", source),
+        }
+    }
+
+    match args.raw("format").unwrap_or("text") {
+        "text" => {
+            for summary in &summaries {
+                println!("{}", summary.to_text());
+            }
+        }
+        "json" => println!("{}", serde_json::to_string_pretty(&summaries)?),
+        "csv" => {
+            println!("{}", CSV_HEADER);
+            for summary in &summaries {
+                println!("{}", summary.to_csv());
+            }
+        }
+        format => {
+            eprintln!("Invalid format {:?}. Choose text, json or csv.", format);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use acn_final_rs::cli::Args;
+use acn_final_rs::coldstart::{ColdStartConfig, DEFAULT_KEEP_ALIVE};
+use acn_final_rs::dispatch::{Algorithm, Bootstrap, Estimator};
+use acn_final_rs::functions::Hosts;
+use acn_final_rs::loadgen::{self, Arrivals};
+use acn_final_rs::protocol::DEFAULT_FUNCTION;
+use acn_final_rs::service::ServiceTime;
+use acn_final_rs::sim::{self, SimComputer, SimConfig};
+use acn_final_rs::slo::{SloStats, Slos};
+use acn_final_rs::stats;
+use acn_final_rs::workload::SizeDistribution;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// 1 ms per hop each way unless --netem says otherwise
+const DEFAULT_NETWORK: &str = "1";
+
+// Fewer than e_report's default: simulated runs are often millions of tasks
+const DEFAULT_RESAMPLES: usize = 100;
+
+fn main() -> std::io::Result<()> {
+    let args = Args::from_env();
+    if args.positional.is_empty() || (args.positional.len() < 2 && !args.has("nodes")) {
+        eprintln!(
+            "Usage: {} <algorithm> <speed:hops[:workers][@f1,f2]>... [--nodes N] [--speed S] [--max-hops H] [--workers W] [--mode closed|open] [--clients N] [--tasks M] [--rate R] [--size SPEC] [--function NAME] [--service SPEC] [--netem LINK[,HOPS=LINK...]] [--cold-start-ms N] [--keep-alive-ms N] [--max-instances N] [--estimator SPEC] [--bootstrap optimistic|average] [--slo SLO[,FUNCTION=SLO...]] [--seed N] [--resamples N] [--output FILE]",
+            args.program
+        );
+        eprintln!(
+            "  --nodes N adds N e-computers of speed S with W workers at 1..=H hops, round robin"
+        );
+        eprintln!("  LINK: LATENCY_MS[:JITTER_MS[:LOSS[:RTO_MS]]] per hop (default 1), HOPS=LINK for e-computers at HOPS hops");
+        eprintln!("  SPEC: ewma[:ALPHA], mean[:N], percentile[:P[:N]] or decay[:HALF_LIFE_MS] (default ewma:0.95)");
+        eprintln!("  SLO: pP:TARGET_MS, e.g. p95:300, counted and used by the SLO algorithm");
+        eprintln!("  closed: N clients each send M tasks back to back (default 1 x 100)");
+        eprintln!("  open:   M tasks arrive as a Poisson process at R tasks per second");
+        std::process::exit(1);
+    }
+
+    let algorithm: Algorithm = args.positional[0].parse().unwrap_or_else(|e: String| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut computers: Vec<SimComputer> = args.positional[1..]
+        .iter()
+        .map(|spec| {
+            spec.parse().unwrap_or_else(|e: String| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    let nodes: usize = args.get_or("nodes", 0);
+    let max_hops: u32 = args.get_or("max-hops", 3).max(1);
+    for i in 0..nodes {
+        computers.push(SimComputer {
+            speed: args.get_or("speed", 100000),
+            hops: 1 + i as u32 % max_hops,
+            workers: args.get_or("workers", 1).max(1),
+            hosts: Hosts::All,
+        });
+    }
+
+    let tasks: usize = args.get_or("tasks", 100);
+    let (arrivals, default_output) = match args.raw("mode").unwrap_or("closed") {
+        "closed" => {
+            let clients: usize = args.get_or("clients", 1);
+            let arrivals = Arrivals::Closed {
+                clients,
+                tasks_per_client: tasks,
+            };
+            (arrivals, format!("sim-times-{}.log", clients))
+        }
+        "open" => {
+            let rate: f64 = args.get_or("rate", 10.0);
+            if !(rate > 0.0 && rate.is_finite()) {
+                eprintln!("Arrival rate must be a positive number");
+                std::process::exit(1);
+            }
+            let arrivals = Arrivals::Poisson { rate, tasks };
+            (arrivals, format!("sim-times-poisson-{}.log", rate))
+        }
+        mode => {
+            eprintln!("Invalid mode {:?}. Choose closed or open.", mode);
+            std::process::exit(1);
+        }
+    };
+
+    let config = SimConfig {
+        algorithm,
+        computers,
+        arrivals,
+        sizes: args.get_or("size", SizeDistribution::Fixed(5000)),
+        function: args.get_or("function", DEFAULT_FUNCTION.to_string()),
+        service: args.get_or("service", ServiceTime::Deterministic),
+        network: args.get_or("netem", DEFAULT_NETWORK.parse().unwrap()),
+        cold_start: ColdStartConfig {
+            penalty: Duration::from_millis(args.get_or("cold-start-ms", 0)),
+            keep_alive: args
+                .get("keep-alive-ms")
+                .map_or(DEFAULT_KEEP_ALIVE, Duration::from_millis),
+            max_instances: args.get("max-instances"),
+        },
+        estimator: args.get_or("estimator", Estimator::default()),
+        bootstrap: args.get_or("bootstrap", Bootstrap::default()),
+        slos: args.get_or("slo", Slos::default()),
+        seed: args.get_or("seed", 0),
+    };
+    println!(
+        "Simulating {} over {} e-computer(s), {:?}, task size {}, service time {}, network {}, estimator {} ({} bootstrap)",
+        config.algorithm,
+        config.computers.len(),
+        config.arrivals,
+        config.sizes,
+        config.service,
+        config.network,
+        config.estimator,
+        config.bootstrap
+    );
+
+    let started = Instant::now();
+    let outcome = sim::run(&config).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let wall_time = started.elapsed();
+
+    println!(
+        "{} tasks completed, {} without a destination, {} cold start(s) in {:.3?} of virtual time",
+        outcome.latencies.len(),
+        outcome.failures,
+        outcome.cold_starts,
+        outcome.elapsed
+    );
+    println!(
+        "{} events in {:.2?} of wall time ({:.0} events/s)",
+        outcome.events,
+        wall_time,
+        outcome.events as f64 / wall_time.as_secs_f64()
+    );
+
+    // Where the tasks went, grouped by distance from the e-router
+    let mut by_hops: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+    for (computer, assigned) in config.computers.iter().zip(&outcome.assigned) {
+        let entry = by_hops.entry(computer.hops).or_default();
+        entry.0 += 1;
+        entry.1 += assigned;
+    }
+    for (hops, (computers, assigned)) in by_hops {
+        println!(
+            "  {} hop(s): {} e-computer(s), {} task(s)",
+            hops, computers, assigned
+        );
+    }
+
+    // Tasks without a destination missed the objective too
+    if let Some(slo) = config.slos.get(&config.function) {
+        let mut slo_stats = SloStats::default();
+        for latency in &outcome.latencies {
+            slo_stats.record(Duration::from_secs_f64(*latency) <= slo.target);
+        }
+        for _ in 0..outcome.failures {
+            slo_stats.record(false);
+        }
+        println!(
+            "SLO {} for {}: {} of {} task(s) over target ({:.2}%), {}",
+            slo,
+            config.function,
+            slo_stats.violations,
+            slo_stats.tasks,
+            slo_stats.violation_rate() * 100.0,
+            if slo_stats.is_met(&slo) {
+                "met"
+            } else {
+                "missed"
+            }
+        );
+    }
+
+    if let Some(summary) = stats::summarize(
+        &algorithm.to_string(),
+        &outcome.latencies,
+        args.get_or("resamples", DEFAULT_RESAMPLES),
+        config.seed,
+    ) {
+        println!("{}", summary.to_text());
+    }
+
+    let output = args.get_or("output", default_output);
+    loadgen::write_log(&output, &outcome.latencies)?;
+    println!("Latencies written to {}", output);
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// Minimal command line handling shared by the binaries: positional arguments
+// plus `--name value` options. A bare `--name` is a flag.
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub struct Args {
+    pub program: String,
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    pub fn from_env() -> Self {
+        Args::parse(env::args())
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut args = args.into_iter().peekable();
+        let program = args.next().unwrap_or_default();
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = match args.peek() {
+                        Some(next) if !next.starts_with("--") => args.next().unwrap(),
+                        _ => "true".to_string(),
+                    };
+                    options.insert(name.to_string(), value);
+                }
+                None => positional.push(arg),
+            }
+        }
+        Args {
+            program,
+            positional,
+            options,
+        }
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    // Parse option `name`, exiting with a message if it is malformed
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        self.raw(name).map(|value| {
+            value.parse().unwrap_or_else(|error| {
+                eprintln!("Invalid value {:?} for --{}: {}", value, name, error);
+                std::process::exit(1);
+            })
+        })
+    }
+
+    pub fn get_or<T: FromStr>(&self, name: &str, default: T) -> T
+    where
+        T::Err: Display,
+    {
+        self.get(name).unwrap_or(default)
+    }
+}
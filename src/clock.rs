@@ -0,0 +1,45 @@
+// Time as seen by the routing algorithms. The e-routers run on the system
+// clock; the discrete-event simulator hands them a virtual clock it advances
+// itself, so the same dispatcher code runs in both.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub enum Clock {
+    // Real time since the clock was created
+    System(Instant),
+    // Virtual time in nanoseconds, only moved by `set`
+    Virtual(Arc<AtomicU64>),
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        Clock::System(Instant::now())
+    }
+
+    pub fn virtual_clock() -> Self {
+        Clock::Virtual(Arc::new(AtomicU64::new(0)))
+    }
+
+    pub fn now(&self) -> Duration {
+        match self {
+            Clock::System(start) => start.elapsed(),
+            Clock::Virtual(nanos) => Duration::from_nanos(nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    // Move a virtual clock to `now`; the system clock can't be moved
+    pub fn set(&self, now: Duration) {
+        match self {
+            Clock::System(_) => panic!("The system clock can't be set"),
+            Clock::Virtual(nanos) => nanos.store(now.as_nanos() as u64, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::system()
+    }
+}
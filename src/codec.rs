@@ -0,0 +1,291 @@
+// Length-prefixed framing shared by the e-router, the e-computers and the clients.
+// Every message on the wire is a 4-byte big-endian length followed by that many
+// bytes of JSON, so payloads of any size survive split TCP segments.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const HEADER_LEN: usize = 4;
+
+// Upper bound on a single frame, so a corrupt header can't make us allocate gigabytes
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+fn frame_too_large(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("frame of {} bytes exceeds limit of {}", len, MAX_FRAME_LEN),
+    )
+}
+
+// Write one frame. Header and payload go out in a single write.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(frame_too_large(payload.len()));
+    }
+    let mut buffer = Vec::with_capacity(HEADER_LEN + payload.len());
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    writer.write_all(&buffer)?;
+    writer.flush()
+}
+
+// Read one frame, blocking until the whole payload has arrived
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+// Serialize a message as JSON and send it as one frame
+pub fn send<W: Write, T: Serialize>(writer: &mut W, message: &T) -> io::Result<()> {
+    let payload = serde_json::to_vec(message)?;
+    write_frame(writer, &payload)
+}
+
+// Receive one frame and deserialize it from JSON
+pub fn recv<R: Read, T: DeserializeOwned>(reader: &mut R) -> io::Result<T> {
+    let payload = read_frame(reader)?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+// Like read_frame, but a connection closed cleanly between frames yields None
+// instead of an error. Used by long-lived connections that carry many requests.
+pub fn try_read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+// Multiplexed frames carry a request id in the first 8 bytes of the payload so
+// many requests can be in flight on one connection and answered out of order.
+pub const REQUEST_ID_LEN: usize = 8;
+
+fn tag(request_id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(REQUEST_ID_LEN + payload.len());
+    tagged.extend_from_slice(&request_id.to_be_bytes());
+    tagged.extend_from_slice(payload);
+    tagged
+}
+
+fn untag(mut tagged: Vec<u8>) -> io::Result<(u64, Vec<u8>)> {
+    if tagged.len() < REQUEST_ID_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "tagged frame shorter than its request id",
+        ));
+    }
+    let payload = tagged.split_off(REQUEST_ID_LEN);
+    let request_id = u64::from_be_bytes(tagged.try_into().unwrap());
+    Ok((request_id, payload))
+}
+
+pub fn write_tagged_frame<W: Write>(
+    writer: &mut W,
+    request_id: u64,
+    payload: &[u8],
+) -> io::Result<()> {
+    write_frame(writer, &tag(request_id, payload))
+}
+
+pub fn try_read_tagged_frame<R: Read>(reader: &mut R) -> io::Result<Option<(u64, Vec<u8>)>> {
+    try_read_frame(reader)?.map(untag).transpose()
+}
+
+// Async counterparts of the functions above, used by the tokio e-router and clients
+
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    payload: &[u8],
+) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(frame_too_large(payload.len()));
+    }
+    let mut buffer = Vec::with_capacity(HEADER_LEN + payload.len());
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    writer.write_all(&buffer).await?;
+    writer.flush().await
+}
+
+pub async fn read_frame_async<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    try_read_frame_async(reader)
+        .await?
+        .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+pub async fn try_read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        match reader.read(&mut header[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+pub async fn write_tagged_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    request_id: u64,
+    payload: &[u8],
+) -> io::Result<()> {
+    write_frame_async(writer, &tag(request_id, payload)).await
+}
+
+pub async fn try_read_tagged_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<(u64, Vec<u8>)>> {
+    try_read_frame_async(reader).await?.map(untag).transpose()
+}
+
+pub async fn send_async<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+) -> io::Result<()> {
+    let payload = serde_json::to_vec(message)?;
+    write_frame_async(writer, &payload).await
+}
+
+pub async fn recv_async<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    reader: &mut R,
+) -> io::Result<T> {
+    let payload = read_frame_async(reader).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out at most `chunk` bytes per read, like a socket delivering
+    // split segments
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn framed(payloads: &[&[u8]]) -> Vec<u8> {
+        let mut wire = Vec::new();
+        for payload in payloads {
+            write_frame(&mut wire, payload).unwrap();
+        }
+        wire
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let wire = framed(&[b"hello", b"", b"{\"id\":\"1\"}"]);
+        let mut reader = wire.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), b"hello");
+        assert_eq!(try_read_frame(&mut reader).unwrap().unwrap(), b"");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"{\"id\":\"1\"}");
+        assert!(try_read_frame(&mut reader).unwrap().is_none());
+
+        let mut wire = Vec::new();
+        write_tagged_frame(&mut wire, 42, b"task").unwrap();
+        let mut reader = wire.as_slice();
+        assert_eq!(
+            try_read_tagged_frame(&mut reader).unwrap(),
+            Some((42, b"task".to_vec()))
+        );
+    }
+
+    #[test]
+    fn frames_survive_split_reads() {
+        let large = vec![7; 100_000];
+        let wire = framed(&[b"first", &large]);
+        for chunk in [1, 3, 4, 5, 1000] {
+            let mut reader = Trickle { data: &wire, chunk };
+            assert_eq!(try_read_frame(&mut reader).unwrap().unwrap(), b"first");
+            assert_eq!(read_frame(&mut reader).unwrap(), large);
+            assert!(try_read_frame(&mut reader).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn a_connection_closed_mid_frame_is_an_error() {
+        let wire = framed(&[b"payload"]);
+        for cut in [2, HEADER_LEN + 3] {
+            let mut reader = &wire[..cut];
+            let error = try_read_frame(&mut reader).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let header = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        let mut reader = header.as_slice();
+        let error = read_frame(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut reader = header.as_slice();
+        assert!(try_read_frame(&mut reader).is_err());
+
+        let mut wire = Vec::new();
+        let error = write_frame(&mut wire, &vec![0; MAX_FRAME_LEN + 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(wire.is_empty());
+    }
+
+    #[tokio::test]
+    async fn async_frames_survive_split_reads() {
+        // A tiny pipe forces the reader to see the frames in pieces
+        let (mut writer, mut reader) = tokio::io::duplex(3);
+        let large = vec![9; 10_000];
+        let sent = large.clone();
+        let writing = tokio::spawn(async move {
+            write_tagged_frame_async(&mut writer, 7, b"first")
+                .await
+                .unwrap();
+            write_frame_async(&mut writer, &sent).await.unwrap();
+        });
+        assert_eq!(
+            try_read_tagged_frame_async(&mut reader).await.unwrap(),
+            Some((7, b"first".to_vec()))
+        );
+        assert_eq!(read_frame_async(&mut reader).await.unwrap(), large);
+        writing.await.unwrap();
+        assert!(try_read_frame_async(&mut reader).await.unwrap().is_none());
+    }
+}
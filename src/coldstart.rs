@@ -0,0 +1,166 @@
+// Warm-instance bookkeeping for the e-computers.
+// Every invocation needs an instance of its function. An idle instance that
+// was used within the keep-alive TTL is reused (warm start); otherwise a new
+// one is started and the invocation pays the cold-start penalty. At most
+// `max_instances` idle instances of a function are kept, the least recently
+// used ones being evicted first. Times are durations since an arbitrary epoch
+// so the same pool works on a virtual clock.
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColdStartConfig {
+    pub penalty: Duration,
+    pub keep_alive: Duration,
+    // Idle instances kept per function; None for no limit
+    pub max_instances: Option<usize>,
+}
+
+impl Default for ColdStartConfig {
+    fn default() -> Self {
+        ColdStartConfig {
+            penalty: Duration::ZERO,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            max_instances: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InstancePool {
+    config: ColdStartConfig,
+    // Function name -> times its idle instances were last released, oldest first
+    idle: HashMap<String, Vec<Duration>>,
+}
+
+impl InstancePool {
+    pub fn new(config: ColdStartConfig) -> Self {
+        InstancePool {
+            config,
+            idle: HashMap::new(),
+        }
+    }
+
+    // Drop idle instances whose keep-alive TTL has run out
+    pub fn expire(&mut self, now: Duration) {
+        let keep_alive = self.config.keep_alive;
+        for instances in self.idle.values_mut() {
+            instances.retain(|released| now.saturating_sub(*released) < keep_alive);
+        }
+        self.idle.retain(|_, instances| !instances.is_empty());
+    }
+
+    // Claim an instance of `function`. Returns the start-up delay to apply and
+    // whether it was a cold start.
+    pub fn acquire(&mut self, function: &str, now: Duration) -> (Duration, bool) {
+        self.expire(now);
+        match self
+            .idle
+            .get_mut(function)
+            .and_then(|instances| instances.pop())
+        {
+            // Most recently used instance first, so the others can expire
+            Some(_) => (Duration::ZERO, false),
+            None => (self.config.penalty, true),
+        }
+    }
+
+    // Return an instance of `function` to the idle pool once its invocation is done
+    pub fn release(&mut self, function: &str, now: Duration) {
+        let instances = self.idle.entry(function.to_string()).or_default();
+        instances.push(now);
+        if let Some(max) = self.config.max_instances {
+            let evicted = instances.len().saturating_sub(max);
+            instances.drain(..evicted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn pool(max_instances: Option<usize>) -> InstancePool {
+        InstancePool::new(ColdStartConfig {
+            penalty: ms(200),
+            keep_alive: ms(1000),
+            max_instances,
+        })
+    }
+
+    #[test]
+    fn released_instances_start_warm() {
+        let clock = Clock::virtual_clock();
+        let mut pool = pool(None);
+        assert_eq!(pool.acquire("resize", clock.now()), (ms(200), true));
+        clock.set(ms(300));
+        pool.release("resize", clock.now());
+        assert_eq!(pool.acquire("resize", clock.now()), (Duration::ZERO, false));
+        // It is in use again, so a second invocation needs another one
+        assert_eq!(pool.acquire("resize", clock.now()), (ms(200), true));
+        // Instances only serve their own function
+        pool.release("resize", clock.now());
+        assert_eq!(pool.acquire("thumbnail", clock.now()), (ms(200), true));
+    }
+
+    #[test]
+    fn idle_instances_expire_after_the_keep_alive() {
+        let clock = Clock::virtual_clock();
+        let mut pool = pool(None);
+        pool.release("resize", clock.now());
+        clock.set(ms(999));
+        assert_eq!(pool.acquire("resize", clock.now()), (Duration::ZERO, false));
+        pool.release("resize", clock.now());
+        clock.set(ms(1998));
+        pool.expire(clock.now());
+        assert_eq!(pool.idle["resize"], [ms(999)]);
+        clock.set(ms(1999));
+        pool.expire(clock.now());
+        assert!(pool.idle.is_empty());
+        assert_eq!(pool.acquire("resize", clock.now()), (ms(200), true));
+    }
+
+    #[test]
+    fn the_most_recently_used_instance_is_reused() {
+        let clock = Clock::virtual_clock();
+        let mut pool = pool(None);
+        pool.release("resize", clock.now());
+        clock.set(ms(600));
+        pool.release("resize", clock.now());
+        assert_eq!(pool.acquire("resize", clock.now()), (Duration::ZERO, false));
+        // The older one runs out while the other is busy
+        clock.set(ms(1000));
+        pool.expire(clock.now());
+        assert!(pool.idle.is_empty());
+    }
+
+    #[test]
+    fn instances_past_the_limit_are_evicted_oldest_first() {
+        let clock = Clock::virtual_clock();
+        let mut limited = pool(Some(2));
+        for released in [100, 200, 300] {
+            clock.set(ms(released));
+            limited.release("resize", clock.now());
+        }
+        assert_eq!(limited.idle["resize"], [ms(200), ms(300)]);
+        for _ in 0..2 {
+            assert_eq!(
+                limited.acquire("resize", clock.now()),
+                (Duration::ZERO, false)
+            );
+        }
+        assert_eq!(limited.acquire("resize", clock.now()), (ms(200), true));
+
+        // Without room for any, every start is cold
+        let mut none = pool(Some(0));
+        none.release("resize", clock.now());
+        assert_eq!(none.acquire("resize", clock.now()), (ms(200), true));
+    }
+}
@@ -0,0 +1,34 @@
+use super::{Destination, Dispatcher};
+use std::time::Duration;
+
+// AC: always send to the e-computer with the fewest hops
+#[derive(Default)]
+pub struct AlwaysClosest {
+    destinations: Vec<Destination>,
+}
+
+impl AlwaysClosest {
+    pub fn new() -> Self {
+        AlwaysClosest::default()
+    }
+}
+
+impl Dispatcher for AlwaysClosest {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        self.destinations
+            .iter()
+            .filter(|d| !exclude.contains(&d.port))
+            .min_by_key(|d| d.hops)
+            .map(|d| d.port)
+    }
+
+    fn on_completion(&mut self, _: u16, _: Duration) {}
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.destinations.push(destination);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.destinations.retain(|d| d.port != port);
+    }
+}
@@ -0,0 +1,278 @@
+// How a weight table turns an e-computer's latency samples into one estimate,
+// and what it assumes before the first sample.
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Smoothing factor of the latency EWMA, from the paper
+pub const DEFAULT_ALPHA: f64 = 0.95;
+pub const DEFAULT_WINDOW: usize = 20;
+pub const DEFAULT_PERCENTILE: f64 = 95.0;
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(1);
+
+// Given as `ewma[:ALPHA]`, `mean[:N]`, `percentile[:P[:N]]` or `decay[:HALF_LIFE_MS]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Estimator {
+    // Exponentially weighted moving average: each sample moves the estimate by 1 - alpha
+    Ewma { alpha: f64 },
+    // Mean of the last `window` samples
+    WindowMean { window: usize },
+    // P-th percentile (0-100) of the last `window` samples
+    WindowPercentile { percentile: f64, window: usize },
+    // Average of all samples, each weighted down by half every `half_life` of
+    // age, so an e-computer that has been quiet for a while is judged on its
+    // next samples rather than on old ones
+    DecayedEwma { half_life: Duration },
+}
+
+impl Default for Estimator {
+    fn default() -> Self {
+        Estimator::Ewma {
+            alpha: DEFAULT_ALPHA,
+        }
+    }
+}
+
+// What an e-computer without samples yet is estimated at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bootstrap {
+    // Zero, so latency-driven policies try every new e-computer first
+    #[default]
+    Optimistic,
+    // The average of the known estimates (zero if there are none), so a new
+    // e-computer gets an ordinary share rather than a rush of tasks
+    Average,
+}
+
+// The samples behind one e-computer's estimate
+#[derive(Clone, Debug, Default)]
+pub struct Samples {
+    // None until the first sample: the explicit unknown state
+    value: Option<f64>,
+    window: VecDeque<f64>,
+    // Decayed sum of samples and of their weights, and when they were last decayed
+    sum: f64,
+    weight: f64,
+    updated: Duration,
+}
+
+impl Samples {
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+impl Estimator {
+    // Add a sample (in seconds) taken at `now`
+    pub fn update(&self, samples: &mut Samples, sample: f64, now: Duration) {
+        samples.value = Some(match *self {
+            Estimator::Ewma { alpha } => match samples.value {
+                Some(value) => alpha * value + (1.0 - alpha) * sample,
+                None => sample,
+            },
+            Estimator::WindowMean { window } => {
+                push(&mut samples.window, sample, window);
+                samples.window.iter().sum::<f64>() / samples.window.len() as f64
+            }
+            Estimator::WindowPercentile { percentile, window } => {
+                push(&mut samples.window, sample, window);
+                let mut sorted: Vec<f64> = samples.window.iter().copied().collect();
+                sorted.sort_by(f64::total_cmp);
+                // Nearest rank
+                let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+                sorted[rank.clamp(1, sorted.len()) - 1]
+            }
+            Estimator::DecayedEwma { half_life } => {
+                let age = now.saturating_sub(samples.updated).as_secs_f64();
+                let decay = 0.5f64.powf(age / half_life.as_secs_f64());
+                samples.sum = samples.sum * decay + sample;
+                samples.weight = samples.weight * decay + 1.0;
+                samples.updated = now;
+                samples.sum / samples.weight
+            }
+        });
+    }
+
+    // Forget the samples and start again from `sample`
+    pub fn reset(&self, samples: &mut Samples, sample: f64, now: Duration) {
+        *samples = Samples::default();
+        self.update(samples, sample, now);
+    }
+}
+
+fn push(window: &mut VecDeque<f64>, sample: f64, size: usize) {
+    window.push_back(sample);
+    while window.len() > size.max(1) {
+        window.pop_front();
+    }
+}
+
+impl FromStr for Estimator {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parts = spec.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let fields: Vec<&str> = parts.collect();
+        let field = |i: usize| -> Result<Option<f64>, String> {
+            fields
+                .get(i)
+                .map(|p| {
+                    p.parse::<f64>()
+                        .ok()
+                        .filter(|v| v.is_finite() && *v >= 0.0)
+                        .ok_or_else(|| format!("Invalid field {:?} in estimator {:?}", p, spec))
+                })
+                .transpose()
+        };
+        let window = |i: usize| -> Result<usize, String> {
+            match field(i)? {
+                Some(n) if n < 1.0 => Err(format!("Window must be at least 1 in {:?}", spec)),
+                Some(n) => Ok(n as usize),
+                None => Ok(DEFAULT_WINDOW),
+            }
+        };
+        let max_fields = match kind {
+            "percentile" => 2,
+            _ => 1,
+        };
+        if fields.len() > max_fields {
+            return Err(format!("Too many fields in estimator {:?}", spec));
+        }
+        match kind {
+            "ewma" => {
+                let alpha = field(0)?.unwrap_or(DEFAULT_ALPHA);
+                if alpha >= 1.0 {
+                    return Err(format!("Alpha must be below 1 in {:?}", spec));
+                }
+                Ok(Estimator::Ewma { alpha })
+            }
+            "mean" => Ok(Estimator::WindowMean { window: window(0)? }),
+            "percentile" => {
+                let percentile = field(0)?.unwrap_or(DEFAULT_PERCENTILE);
+                if percentile > 100.0 {
+                    return Err(format!("Percentile must be at most 100 in {:?}", spec));
+                }
+                Ok(Estimator::WindowPercentile {
+                    percentile,
+                    window: window(1)?,
+                })
+            }
+            "decay" => {
+                let half_life = match field(0)? {
+                    Some(ms) => Duration::try_from_secs_f64(ms / 1000.0)
+                        .map_err(|_| format!("Half-life out of range in {:?}", spec))?,
+                    None => DEFAULT_HALF_LIFE,
+                };
+                if half_life.is_zero() {
+                    return Err(format!("Half-life must be positive in {:?}", spec));
+                }
+                Ok(Estimator::DecayedEwma { half_life })
+            }
+            _ => Err(format!(
+                "Invalid estimator {:?}. Choose ewma[:ALPHA], mean[:N], percentile[:P[:N]] or decay[:HALF_LIFE_MS].",
+                spec
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Estimator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Estimator::Ewma { alpha } => write!(f, "ewma:{}", alpha),
+            Estimator::WindowMean { window } => write!(f, "mean:{}", window),
+            Estimator::WindowPercentile { percentile, window } => {
+                write!(f, "percentile:{}:{}", percentile, window)
+            }
+            Estimator::DecayedEwma { half_life } => {
+                write!(f, "decay:{}", half_life.as_secs_f64() * 1000.0)
+            }
+        }
+    }
+}
+
+impl FromStr for Bootstrap {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "optimistic" => Ok(Bootstrap::Optimistic),
+            "average" => Ok(Bootstrap::Average),
+            _ => Err(format!(
+                "Invalid bootstrap {:?}. Choose optimistic or average.",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bootstrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Bootstrap::Optimistic => "optimistic",
+            Bootstrap::Average => "average",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(estimator: Estimator, samples: &[(f64, u64)]) -> Option<f64> {
+        let mut state = Samples::default();
+        for &(sample, at_ms) in samples {
+            estimator.update(&mut state, sample, Duration::from_millis(at_ms));
+        }
+        state.value()
+    }
+
+    #[test]
+    fn unknown_until_the_first_sample() {
+        assert_eq!(estimate(Estimator::default(), &[]), None);
+        assert_eq!(estimate(Estimator::default(), &[(0.2, 0)]), Some(0.2));
+    }
+
+    #[test]
+    fn windows_keep_the_last_samples() {
+        let samples = [(9.0, 0), (1.0, 0), (2.0, 0), (3.0, 0)];
+        let mean = Estimator::WindowMean { window: 3 };
+        assert_eq!(estimate(mean, &samples), Some(2.0));
+        let max = Estimator::WindowPercentile {
+            percentile: 100.0,
+            window: 3,
+        };
+        assert_eq!(estimate(max, &samples), Some(3.0));
+    }
+
+    #[test]
+    fn decay_halves_old_samples_every_half_life() {
+        let decay = Estimator::DecayedEwma {
+            half_life: Duration::from_secs(1),
+        };
+        // The first sample weighs 1/2 against the second's 1
+        let value = estimate(decay, &[(3.0, 0), (6.0, 1000)]).unwrap();
+        assert!((value - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn specs_round_trip() {
+        for spec in ["ewma:0.5", "mean:10", "percentile:99:50", "decay:250"] {
+            assert_eq!(spec.parse::<Estimator>().unwrap().to_string(), spec);
+        }
+        assert!("ewma:1".parse::<Estimator>().is_err());
+        assert!("mean:0".parse::<Estimator>().is_err());
+        assert!("median".parse::<Estimator>().is_err());
+        for spec in [
+            "decay:0",
+            "decay:inf",
+            "decay:NaN",
+            "decay:1e300",
+            "mean:inf",
+        ] {
+            assert!(spec.parse::<Estimator>().is_err(), "{}", spec);
+        }
+    }
+}
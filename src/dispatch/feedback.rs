@@ -0,0 +1,421 @@
+// Policies driven by the load e-computers report with their responses (see
+// `protocol::Feedback`), combined with the e-router's own outstanding requests
+// so they don't all pile onto the same e-computer between two reports.
+use super::{Destination, Dispatcher, Outstanding, WeightTable};
+use crate::clock::Clock;
+use crate::protocol::Feedback;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// What an e-computer last reported. Before its first report it is taken to
+// have one worker and no load from anyone else.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Report {
+    pub(super) workers: usize,
+    // Tasks it had that this e-router didn't send, e.g. from other e-routers
+    external: usize,
+    // It failed a task and is passed over until then, or until it reports again
+    failed_until: Duration,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            workers: 1,
+            external: 0,
+            failed_until: Duration::ZERO,
+        }
+    }
+}
+
+impl Report {
+    // Tasks there right now as far as we can tell: `ours` in flight, which we
+    // know exactly, plus the others it last reported
+    pub(super) fn backlog(&self, ours: usize) -> usize {
+        ours + self.external
+    }
+
+    pub(super) fn has_failed(&self, now: Duration) -> bool {
+        now < self.failed_until
+    }
+}
+
+// Reports kept in the order the e-computers were added, like `WeightTable`,
+// so a policy adding them in step with its tables can scan both together
+// without a lookup per e-computer
+#[derive(Default)]
+pub(super) struct Reports {
+    reports: Vec<(u16, Report)>,
+    index: HashMap<u16, usize>,
+}
+
+impl Reports {
+    pub(super) fn insert(&mut self, port: u16) {
+        if !self.index.contains_key(&port) {
+            self.index.insert(port, self.reports.len());
+            self.reports.push((port, Report::default()));
+        }
+    }
+
+    pub(super) fn remove(&mut self, port: u16) {
+        if let Some(i) = self.index.remove(&port) {
+            self.reports.remove(i);
+            for (j, (p, _)) in self.reports.iter().enumerate().skip(i) {
+                self.index.insert(*p, j);
+            }
+        }
+    }
+
+    // `outstanding` must not be locked by the caller
+    pub(super) fn record(&mut self, port: u16, feedback: &Feedback, outstanding: &Outstanding) {
+        let Some(&i) = self.index.get(&port) else {
+            return;
+        };
+        // Our own tasks still there are the outstanding ones, the rest of the backlog isn't ours
+        let ours = outstanding.in_flight(port);
+        self.reports[i].1 = Report {
+            workers: feedback.workers.max(1),
+            external: feedback.backlog().saturating_sub(ours),
+            failed_until: Duration::ZERO,
+        };
+    }
+
+    // Pass `port` over until `until`, its last report being no guide to a
+    // computer that failed a task
+    pub(super) fn fail(&mut self, port: u16, until: Duration) {
+        if let Some(&i) = self.index.get(&port) {
+            self.reports[i].1.failed_until = until;
+        }
+    }
+
+    pub(super) fn get(&self, port: u16) -> Report {
+        self.index
+            .get(&port)
+            .map(|&i| self.reports[i].1)
+            .unwrap_or_default()
+    }
+
+    // (port, report) pairs in the order the ports were inserted
+    pub(super) fn iter(&self) -> impl Iterator<Item = (u16, Report)> + '_ {
+        self.reports.iter().copied()
+    }
+}
+
+// LQ: send to the e-computer with the fewest tasks per worker, ties broken by
+// the lowest service time it reported. One that failed a task is passed over
+// for the penalty, unless there is nothing else.
+#[derive(Default)]
+pub struct LeastQueue {
+    // Smoothed service time in seconds, in step with `reports`
+    service: WeightTable,
+    reports: Reports,
+    outstanding: Outstanding,
+    clock: Clock,
+}
+
+impl LeastQueue {
+    pub fn new() -> Self {
+        LeastQueue::default()
+    }
+
+    pub fn with_environment(outstanding: Outstanding, clock: Clock, weights: WeightTable) -> Self {
+        LeastQueue {
+            service: weights,
+            reports: Reports::default(),
+            outstanding,
+            clock,
+        }
+    }
+}
+
+impl Dispatcher for LeastQueue {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.clock.now();
+        let table = self.outstanding.lock();
+        self.service
+            .iter()
+            .zip(self.reports.iter())
+            .filter(|((port, _), _)| !exclude.contains(port))
+            .map(|((port, service), (_, report))| {
+                let per_worker =
+                    report.backlog(table.in_flight(port)) as f64 / report.workers as f64;
+                (port, report.has_failed(now), per_worker, service)
+            })
+            .min_by(|a, b| {
+                a.1.cmp(&b.1)
+                    .then(a.2.total_cmp(&b.2))
+                    .then(a.3.total_cmp(&b.3))
+            })
+            .map(|(port, ..)| port)
+    }
+
+    fn on_completion(&mut self, _: u16, _: Duration) {}
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.reports.fail(port, self.clock.now() + penalty);
+    }
+
+    fn on_feedback(&mut self, port: u16, feedback: &Feedback) {
+        self.reports.record(port, feedback, &self.outstanding);
+        if let Some(service) = feedback.service_time() {
+            self.service.update(port, service);
+        }
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.service.insert(destination.port);
+        self.reports.insert(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.service.remove(port);
+        self.reports.remove(port);
+    }
+}
+
+// Expected latency in seconds of a task sent now to an e-computer with
+// `backlog` tasks on `workers` workers: the overhead, the wait until a worker
+// is free and the service time
+fn expected_delay(service: f64, overhead: f64, workers: usize, backlog: usize) -> f64 {
+    // A new task starts once backlog - workers + 1 of those have finished
+    let wait = if backlog < workers {
+        0.0
+    } else {
+        (backlog - workers + 1) as f64 / workers as f64 * service
+    };
+    overhead + wait + service
+}
+
+// ED: send to the e-computer where the task is expected to finish first. The
+// estimate is the network and e-router overhead, the wait until a worker is
+// free given the tasks already there, and the service time, each smoothed
+// from what the e-computers report and what the e-router measures. E-computers
+// that haven't reported yet are estimated per the bootstrap policy; the
+// default, zero, gets each tried once. One that failed a task is passed over
+// for the penalty, unless there is nothing else.
+#[derive(Default)]
+pub struct ExpectedDelay {
+    // Smoothed service time and overhead (latency minus service and queueing
+    // time) in seconds, in step with `reports`
+    service: WeightTable,
+    overhead: WeightTable,
+    reports: Reports,
+    // Service and queueing time of the last response from each e-computer,
+    // to take off its latency
+    last: HashMap<u16, Duration>,
+    outstanding: Outstanding,
+    clock: Clock,
+}
+
+impl ExpectedDelay {
+    pub fn new() -> Self {
+        ExpectedDelay::default()
+    }
+
+    // `weights` is the empty table both estimates start from
+    pub fn with_environment(outstanding: Outstanding, clock: Clock, weights: WeightTable) -> Self {
+        ExpectedDelay {
+            service: weights.clone(),
+            overhead: weights,
+            reports: Reports::default(),
+            last: HashMap::new(),
+            outstanding,
+            clock,
+        }
+    }
+}
+
+impl Dispatcher for ExpectedDelay {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.clock.now();
+        let table = self.outstanding.lock();
+        self.service
+            .iter()
+            .zip(self.overhead.iter())
+            .zip(self.reports.iter())
+            .filter(|(((port, _), _), _)| !exclude.contains(port))
+            .map(|(((port, service), (_, overhead)), (_, report))| {
+                let backlog = report.backlog(table.in_flight(port));
+                let estimate = expected_delay(service, overhead, report.workers, backlog);
+                (port, report.has_failed(now), estimate)
+            })
+            .min_by(|a, b| a.1.cmp(&b.1).then(a.2.total_cmp(&b.2)))
+            .map(|(port, ..)| port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        if let Some(spent) = self.last.remove(&port) {
+            self.overhead.update(port, latency.saturating_sub(spent));
+        }
+    }
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.last.remove(&port);
+        self.reports.fail(port, self.clock.now() + penalty);
+    }
+
+    fn on_feedback(&mut self, port: u16, feedback: &Feedback) {
+        self.reports.record(port, feedback, &self.outstanding);
+        if let Some(service) = feedback.service_time() {
+            let queue = feedback.queue_time().unwrap_or_default();
+            self.service.update(port, service);
+            self.last.insert(port, service.saturating_add(queue));
+        }
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.service.insert(destination.port);
+        self.overhead.insert(destination.port);
+        self.reports.insert(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.service.remove(port);
+        self.overhead.remove(port);
+        self.reports.remove(port);
+        self.last.remove(&port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::test_util::{add, environment, ms};
+    use crate::dispatch::Environment;
+
+    fn lq(environment: &Environment) -> LeastQueue {
+        LeastQueue::with_environment(
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            environment.weights(),
+        )
+    }
+
+    fn ed(environment: &Environment) -> ExpectedDelay {
+        ExpectedDelay::with_environment(
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            environment.weights(),
+        )
+    }
+
+    fn report(workers: usize, backlog: usize, service_ms: f64, queue_ms: f64) -> Feedback {
+        Feedback {
+            queue_len: backlog.saturating_sub(workers),
+            active_workers: backlog.min(workers),
+            workers,
+            service_ms: Some(service_ms),
+            queue_ms: Some(queue_ms),
+        }
+    }
+
+    #[test]
+    fn lq_takes_the_fewest_tasks_per_worker() {
+        let environment = environment();
+        let mut lq = lq(&environment);
+        add(&mut lq, &[1, 2]);
+        // A task for each of four workers against two for one
+        lq.on_feedback(1, &report(4, 4, 300.0, 0.0));
+        lq.on_feedback(2, &report(1, 2, 100.0, 0.0));
+        assert_eq!(lq.select(), Some(1));
+
+        // Equally idle, the faster one wins
+        lq.on_feedback(1, &report(4, 0, 300.0, 0.0));
+        lq.on_feedback(2, &report(1, 0, 100.0, 0.0));
+        assert_eq!(lq.select(), Some(2));
+        // Our own tasks count before the next report comes in
+        let _attempt = environment.outstanding.start("task-1", "f", 2);
+        assert_eq!(lq.select(), Some(1));
+        assert_eq!(lq.select_excluding(&[1]), Some(2));
+    }
+
+    #[test]
+    fn ed_adds_the_wait_for_a_worker_and_the_overhead() {
+        let environment = environment();
+        let mut ed = ed(&environment);
+        add(&mut ed, &[1, 2]);
+        // 100 ms tasks behind two others on one worker: 300 ms in all
+        ed.on_feedback(1, &report(1, 2, 100.0, 0.0));
+        ed.on_completion(1, ms(100));
+        // 250 ms tasks, idle
+        ed.on_feedback(2, &report(1, 0, 250.0, 0.0));
+        ed.on_completion(2, ms(250));
+        assert_eq!(ed.select(), Some(2));
+
+        // With four workers there is no wait
+        ed.on_feedback(1, &report(4, 2, 100.0, 0.0));
+        ed.on_completion(1, ms(100));
+        assert_eq!(ed.select(), Some(1));
+
+        // 200 ms of network on top of 100 ms service and 50 ms queueing
+        ed.on_feedback(1, &report(4, 0, 100.0, 50.0));
+        ed.on_completion(1, ms(350));
+        assert!(ed.overhead.estimate(1) > 0.19);
+        assert_eq!(ed.select(), Some(2));
+    }
+
+    #[test]
+    fn impossible_times_are_ignored() {
+        let environment = environment();
+        let mut lq = lq(&environment);
+        let mut ed = ed(&environment);
+        add(&mut lq, &[1]);
+        add(&mut ed, &[1]);
+        for (service_ms, queue_ms) in [
+            (-5.0, 0.0),
+            (f64::NAN, 0.0),
+            (f64::INFINITY, 0.0),
+            (1e300, 0.0),
+            (100.0, -5.0),
+        ] {
+            let feedback = report(1, 0, service_ms, queue_ms);
+            lq.on_feedback(1, &feedback);
+            ed.on_feedback(1, &feedback);
+            ed.on_completion(1, ms(100));
+        }
+        // Only the last report had a usable service time
+        assert_eq!(lq.service.get(1), Some(0.1));
+        assert_eq!(ed.service.get(1), Some(0.1));
+        assert_eq!(ed.overhead.get(1), Some(0.0));
+        assert_eq!(lq.select(), Some(1));
+        assert_eq!(ed.select(), Some(1));
+
+        // The e-router drops them as it decodes the response
+        let feedback = report(1, 0, -5.0, f64::NAN).sanitized();
+        assert_eq!((feedback.service_ms, feedback.queue_ms), (None, None));
+        let feedback = report(1, 0, 100.0, 0.0).sanitized();
+        assert_eq!(feedback.service_time(), Some(ms(100)));
+    }
+
+    #[test]
+    fn failed_computers_are_passed_over_until_they_report_again() {
+        let environment = environment();
+        let mut dispatchers: Vec<Box<dyn Dispatcher>> =
+            vec![Box::new(lq(&environment)), Box::new(ed(&environment))];
+        for dispatcher in &mut dispatchers {
+            add(dispatcher.as_mut(), &[1, 2]);
+            // 1 is idle and fast, 2 is busy and slow
+            dispatcher.on_feedback(1, &report(1, 0, 100.0, 0.0));
+            dispatcher.on_completion(1, ms(100));
+            dispatcher.on_feedback(2, &report(1, 3, 300.0, 0.0));
+            dispatcher.on_completion(2, ms(300));
+            assert_eq!(dispatcher.select(), Some(1));
+
+            // 1 goes down: its last report would keep it chosen
+            dispatcher.on_failure(1, ms(5000));
+            assert_eq!(dispatcher.select(), Some(2));
+            // Unless there is nothing else
+            assert_eq!(dispatcher.select_excluding(&[2]), Some(1));
+
+            // Back and answering
+            dispatcher.on_feedback(1, &report(1, 0, 100.0, 0.0));
+            dispatcher.on_completion(1, ms(100));
+            assert_eq!(dispatcher.select(), Some(1));
+
+            // Or the penalty runs out
+            dispatcher.on_failure(1, ms(5000));
+            environment.clock.set(environment.clock.now() + ms(5000));
+            assert_eq!(dispatcher.select(), Some(1));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use super::{Destination, Dispatcher, WeightTable};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+// LI: always send to the e-computer with the lowest smoothed latency
+#[derive(Default)]
+pub struct LeastLatency {
+    weights: WeightTable,
+}
+
+impl LeastLatency {
+    pub fn new() -> Self {
+        LeastLatency::default()
+    }
+
+    pub fn with_weights(weights: WeightTable) -> Self {
+        LeastLatency { weights }
+    }
+}
+
+impl Dispatcher for LeastLatency {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        // The weight table holds the destinations in the order they were added
+        self.weights
+            .iter()
+            .filter(|(port, _)| !exclude.contains(port))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(port, _)| port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        self.weights.update(port, latency);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.weights.insert(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.weights.remove(port);
+    }
+}
+
+// RP: pick randomly with probability proportional to the inverse latency
+pub struct RandomProportional {
+    weights: WeightTable,
+    rng: StdRng,
+}
+
+impl Default for RandomProportional {
+    fn default() -> Self {
+        RandomProportional::with_environment(WeightTable::new(), StdRng::from_entropy())
+    }
+}
+
+impl RandomProportional {
+    pub fn new() -> Self {
+        RandomProportional::default()
+    }
+
+    pub fn with_environment(weights: WeightTable, rng: StdRng) -> Self {
+        RandomProportional { weights, rng }
+    }
+}
+
+impl Dispatcher for RandomProportional {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let candidates = || {
+            self.weights
+                .iter()
+                .filter(|(port, _)| !exclude.contains(port))
+        };
+        // A zero estimate (an unknown e-computer under the optimistic
+        // bootstrap) has an infinite inverse; those win, picked uniformly
+        let zeros = candidates().filter(|(_, w)| *w <= 0.0).count();
+        if zeros > 0 {
+            let pick = self.rng.gen_range(0..zeros);
+            return candidates()
+                .filter(|(_, w)| *w <= 0.0)
+                .nth(pick)
+                .map(|(port, _)| port);
+        }
+        let total_inverse_weight: f64 = candidates().map(|(_, w)| 1.0 / w).sum();
+        let random_value = self.rng.gen::<f64>() * total_inverse_weight;
+        let mut cumulative = 0.0;
+        candidates()
+            .find(|(_, w)| {
+                cumulative += 1.0 / w;
+                cumulative >= random_value
+            })
+            .map(|(port, _)| port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        self.weights.update(port, latency);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.weights.insert(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.weights.remove(port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::test_util::{add, counts, environment, ms};
+    use crate::dispatch::{Bootstrap, Environment};
+    use std::collections::HashMap;
+
+    // RP over e-computers 1..=3 where 1 and 2 answered in 100 and 300 ms and
+    // 3 hasn't answered yet
+    fn random_proportional(bootstrap: Bootstrap) -> RandomProportional {
+        let environment = Environment {
+            bootstrap,
+            ..environment()
+        };
+        let mut rp =
+            RandomProportional::with_environment(environment.weights(), StdRng::seed_from_u64(1));
+        add(&mut rp, &[1, 2, 3]);
+        rp.on_completion(1, ms(100));
+        rp.on_completion(2, ms(300));
+        rp
+    }
+
+    #[test]
+    fn rp_tries_unknown_computers_first_when_optimistic() {
+        let mut rp = random_proportional(Bootstrap::Optimistic);
+        assert_eq!(counts(&mut rp, 50), HashMap::from([(3, 50)]));
+        assert_eq!(rp.select_excluding(&[3]).map(|port| port < 3), Some(true));
+
+        // An answer so fast it rounds to nothing counts the same
+        rp.on_completion(3, Duration::ZERO);
+        assert_eq!(counts(&mut rp, 50), HashMap::from([(3, 50)]));
+    }
+
+    #[test]
+    fn rp_gives_unknown_computers_an_average_share() {
+        let mut rp = random_proportional(Bootstrap::Average);
+        assert!(rp.weights.iter().all(|(_, weight)| weight.is_finite()));
+        // Inverse weights 10 : 3.3 : 5 for 1, 2 and 3 at 200 ms
+        let shares = counts(&mut rp, 3000);
+        assert!(shares[&1] > shares[&3] && shares[&3] > shares[&2]);
+
+        // Nothing known yet: every e-computer stands at zero and is equally likely
+        let environment = Environment {
+            bootstrap: Bootstrap::Average,
+            ..environment()
+        };
+        let mut rp =
+            RandomProportional::with_environment(environment.weights(), StdRng::seed_from_u64(1));
+        add(&mut rp, &[1, 2]);
+        let counts = counts(&mut rp, 200);
+        assert!(counts[&1] > 50 && counts[&2] > 50);
+    }
+}
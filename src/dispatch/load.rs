@@ -0,0 +1,572 @@
+// Load-aware policies. They route on the tasks in flight at each e-computer,
+// as recorded in the `Outstanding` table of the environment they are built in,
+// rather than (or before) latency.
+use super::{Destination, Dispatcher, Outstanding, WeightTable};
+use crate::clock::Clock;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+// Choices sampled per task by P2C
+pub const DEFAULT_CHOICES: usize = 2;
+
+// E-computers that recently failed a task, and until when. A dead e-computer
+// never has anything in flight, so load alone would keep sending it tasks;
+// instead it is passed over for the failure penalty, or until it completes a
+// task, unless there is nowhere else to go.
+#[derive(Debug, Default)]
+struct Penalties {
+    until: HashMap<u16, Duration>,
+    clock: Clock,
+}
+
+impl Penalties {
+    fn new(clock: Clock) -> Self {
+        Penalties {
+            until: HashMap::new(),
+            clock,
+        }
+    }
+
+    fn penalize(&mut self, port: u16, penalty: Duration) {
+        let until = self.clock.now().saturating_add(penalty);
+        self.until.insert(port, until);
+    }
+
+    fn clear(&mut self, port: u16) {
+        self.until.remove(&port);
+    }
+
+    // Whether `port` is passed over at `now`
+    fn is_penalized(&self, port: u16, now: Duration) -> bool {
+        self.until.get(&port).is_some_and(|until| now < *until)
+    }
+
+    fn now(&self) -> Duration {
+        self.clock.now()
+    }
+}
+
+// P2C: sample a few e-computers at random and send to the one with the fewest
+// tasks in flight. Nearly as good as JSQ without looking at every e-computer.
+pub struct PowerOfChoices {
+    choices: usize,
+    ports: Vec<u16>,
+    outstanding: Outstanding,
+    penalties: Penalties,
+    rng: StdRng,
+}
+
+impl Default for PowerOfChoices {
+    fn default() -> Self {
+        PowerOfChoices::with_environment(
+            DEFAULT_CHOICES,
+            Outstanding::default(),
+            Clock::system(),
+            StdRng::from_entropy(),
+        )
+    }
+}
+
+impl PowerOfChoices {
+    pub fn new() -> Self {
+        PowerOfChoices::default()
+    }
+
+    pub fn with_environment(
+        choices: usize,
+        outstanding: Outstanding,
+        clock: Clock,
+        rng: StdRng,
+    ) -> Self {
+        PowerOfChoices {
+            choices: choices.max(1),
+            ports: Vec::new(),
+            outstanding,
+            penalties: Penalties::new(clock),
+            rng,
+        }
+    }
+}
+
+impl Dispatcher for PowerOfChoices {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.penalties.now();
+        let table = self.outstanding.lock();
+        let load = |port: &u16| {
+            (
+                self.penalties.is_penalized(*port, now),
+                table.in_flight(*port),
+            )
+        };
+        if exclude.is_empty() {
+            // Sample positions rather than copying the whole list
+            let len = self.ports.len();
+            rand::seq::index::sample(&mut self.rng, len, self.choices.min(len))
+                .into_iter()
+                .map(|i| self.ports[i])
+                .min_by_key(load)
+        } else {
+            let candidates: Vec<u16> = self
+                .ports
+                .iter()
+                .copied()
+                .filter(|port| !exclude.contains(port))
+                .collect();
+            candidates
+                .choose_multiple(&mut self.rng, self.choices)
+                .copied()
+                .min_by_key(load)
+        }
+    }
+
+    fn on_completion(&mut self, port: u16, _: Duration) {
+        self.penalties.clear(port);
+    }
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.penalties.penalize(port, penalty);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.ports.push(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.ports.retain(|p| *p != port);
+        self.penalties.clear(port);
+    }
+}
+
+// JSQ: send to the e-computer with the fewest tasks in flight, breaking ties
+// at random so idle e-computers share the load
+pub struct JoinShortestQueue {
+    ports: Vec<u16>,
+    outstanding: Outstanding,
+    penalties: Penalties,
+    rng: StdRng,
+}
+
+impl Default for JoinShortestQueue {
+    fn default() -> Self {
+        JoinShortestQueue::with_environment(
+            Outstanding::default(),
+            Clock::system(),
+            StdRng::from_entropy(),
+        )
+    }
+}
+
+impl JoinShortestQueue {
+    pub fn new() -> Self {
+        JoinShortestQueue::default()
+    }
+
+    pub fn with_environment(outstanding: Outstanding, clock: Clock, rng: StdRng) -> Self {
+        JoinShortestQueue {
+            ports: Vec::new(),
+            outstanding,
+            penalties: Penalties::new(clock),
+            rng,
+        }
+    }
+}
+
+impl Dispatcher for JoinShortestQueue {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.penalties.now();
+        let table = self.outstanding.lock();
+        let mut shortest = None;
+        let mut ties = 0;
+        for &port in &self.ports {
+            if exclude.contains(&port) {
+                continue;
+            }
+            let count = (
+                self.penalties.is_penalized(port, now),
+                table.in_flight(port),
+            );
+            match shortest {
+                Some((_, best)) if count > best => {}
+                Some((_, best)) if count == best => {
+                    // Reservoir sampling keeps each tied e-computer equally likely
+                    ties += 1;
+                    if self.rng.gen_range(0..ties) == 0 {
+                        shortest = Some((port, count));
+                    }
+                }
+                _ => {
+                    shortest = Some((port, count));
+                    ties = 1;
+                }
+            }
+        }
+        shortest.map(|(port, _)| port)
+    }
+
+    fn on_completion(&mut self, port: u16, _: Duration) {
+        self.penalties.clear(port);
+    }
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.penalties.penalize(port, penalty);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.ports.push(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.ports.retain(|p| *p != port);
+        self.penalties.clear(port);
+    }
+}
+
+// JIQ: e-computers join an idle queue whenever a task of theirs is over and
+// nothing else is in flight there. Tasks go to the e-computer at the head of
+// the queue, or to a random one when none is idle. One that fails a task
+// doesn't rejoin until it completes one.
+pub struct JoinIdleQueue {
+    ports: Vec<u16>,
+    idle: VecDeque<u16>,
+    // The e-computers in `idle`, so none is queued twice
+    queued: HashSet<u16>,
+    outstanding: Outstanding,
+    penalties: Penalties,
+    rng: StdRng,
+}
+
+impl Default for JoinIdleQueue {
+    fn default() -> Self {
+        JoinIdleQueue::with_environment(
+            Outstanding::default(),
+            Clock::system(),
+            StdRng::from_entropy(),
+        )
+    }
+}
+
+impl JoinIdleQueue {
+    pub fn new() -> Self {
+        JoinIdleQueue::default()
+    }
+
+    pub fn with_environment(outstanding: Outstanding, clock: Clock, rng: StdRng) -> Self {
+        JoinIdleQueue {
+            ports: Vec::new(),
+            idle: VecDeque::new(),
+            queued: HashSet::new(),
+            outstanding,
+            penalties: Penalties::new(clock),
+            rng,
+        }
+    }
+
+    fn finish(&mut self, port: u16) {
+        if self.ports.contains(&port)
+            && self.outstanding.in_flight(port) == 0
+            && self.queued.insert(port)
+        {
+            self.idle.push_back(port);
+        }
+    }
+}
+
+impl Dispatcher for JoinIdleQueue {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        while let Some(i) = self.idle.iter().position(|port| !exclude.contains(port)) {
+            let port = self.idle.remove(i).unwrap();
+            self.queued.remove(&port);
+            // It may have been picked at random, or by another function, since
+            if self.outstanding.in_flight(port) == 0 {
+                return Some(port);
+            }
+        }
+
+        let now = self.penalties.now();
+        let candidates: Vec<u16> = self
+            .ports
+            .iter()
+            .copied()
+            .filter(|port| !exclude.contains(port))
+            .collect();
+        let healthy: Vec<u16> = candidates
+            .iter()
+            .copied()
+            .filter(|port| !self.penalties.is_penalized(*port, now))
+            .collect();
+        if healthy.is_empty() {
+            candidates.choose(&mut self.rng).copied()
+        } else {
+            healthy.choose(&mut self.rng).copied()
+        }
+    }
+
+    fn on_completion(&mut self, port: u16, _: Duration) {
+        self.penalties.clear(port);
+        self.finish(port);
+    }
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.penalties.penalize(port, penalty);
+        if self.queued.remove(&port) {
+            self.idle.retain(|p| *p != port);
+        }
+    }
+
+    fn on_rejected(&mut self, port: u16) {
+        self.finish(port);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        let port = destination.port;
+        self.ports.push(port);
+        if self.queued.insert(port) {
+            self.idle.push_back(port);
+        }
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.ports.retain(|p| *p != port);
+        self.idle.retain(|p| *p != port);
+        self.queued.remove(&port);
+        self.penalties.clear(port);
+    }
+}
+
+// LOR: send to the e-computer with the fewest outstanding requests, and among
+// those to the one with the lowest smoothed latency
+#[derive(Default)]
+pub struct LeastOutstanding {
+    weights: WeightTable,
+    outstanding: Outstanding,
+    penalties: Penalties,
+}
+
+impl LeastOutstanding {
+    pub fn new() -> Self {
+        LeastOutstanding::default()
+    }
+
+    pub fn with_environment(outstanding: Outstanding, clock: Clock, weights: WeightTable) -> Self {
+        LeastOutstanding {
+            weights,
+            outstanding,
+            penalties: Penalties::new(clock),
+        }
+    }
+}
+
+impl Dispatcher for LeastOutstanding {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.penalties.now();
+        let table = self.outstanding.lock();
+        let load = |port: u16| {
+            (
+                self.penalties.is_penalized(port, now),
+                table.in_flight(port),
+            )
+        };
+        self.weights
+            .iter()
+            .filter(|(port, _)| !exclude.contains(port))
+            .min_by(|a, b| load(a.0).cmp(&load(b.0)).then(a.1.total_cmp(&b.1)))
+            .map(|(port, _)| port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        self.penalties.clear(port);
+        self.weights.update(port, latency);
+    }
+
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.penalties.penalize(port, penalty);
+        self.weights.update(port, penalty);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.weights.insert(destination.port);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.weights.remove(port);
+        self.penalties.clear(port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::test_util::{add, busy, counts, environment, ms};
+    use crate::dispatch::Environment;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(1)
+    }
+
+    fn p2c(environment: &Environment, choices: usize) -> PowerOfChoices {
+        PowerOfChoices::with_environment(
+            choices,
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            rng(),
+        )
+    }
+
+    fn jsq(environment: &Environment) -> JoinShortestQueue {
+        JoinShortestQueue::with_environment(
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            rng(),
+        )
+    }
+
+    fn jiq(environment: &Environment) -> JoinIdleQueue {
+        JoinIdleQueue::with_environment(
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            rng(),
+        )
+    }
+
+    fn lor(environment: &Environment) -> LeastOutstanding {
+        LeastOutstanding::with_environment(
+            environment.outstanding.clone(),
+            environment.clock.clone(),
+            environment.weights(),
+        )
+    }
+
+    #[test]
+    fn p2c_takes_the_less_loaded_of_its_choices() {
+        let environment = environment();
+        let outstanding = &environment.outstanding;
+        let mut dispatcher = p2c(&environment, 2);
+        add(&mut dispatcher, &[1, 2, 3]);
+        let _tickets = [
+            busy(outstanding, 1, 3),
+            busy(outstanding, 2, 1),
+            busy(outstanding, 3, 2),
+        ];
+        // Every pair has a less loaded e-computer than 1, and 2 wins every
+        // pair it is in
+        let counts = counts(&mut dispatcher, 300);
+        assert_eq!(counts.get(&1), None);
+        assert!(counts[&2] > counts[&3]);
+        assert_eq!(dispatcher.select_excluding(&[2, 3]), Some(1));
+
+        // With every e-computer sampled it is JSQ
+        let mut all = p2c(&environment, 3);
+        add(&mut all, &[1, 2, 3]);
+        assert_eq!(all.select(), Some(2));
+        assert_eq!(all.select_excluding(&[2]), Some(3));
+    }
+
+    #[test]
+    fn jsq_takes_the_shortest_queue_and_shares_ties() {
+        let environment = environment();
+        let outstanding = &environment.outstanding;
+        let mut jsq = jsq(&environment);
+        add(&mut jsq, &[1, 2, 3]);
+        let _tickets = [busy(outstanding, 1, 2), busy(outstanding, 3, 1)];
+        assert_eq!(jsq.select(), Some(2));
+        assert_eq!(jsq.select_excluding(&[2]), Some(3));
+
+        let _more = busy(outstanding, 2, 1);
+        let counts = counts(&mut jsq, 200);
+        assert_eq!(counts.get(&1), None);
+        assert!(counts[&2] > 50 && counts[&3] > 50);
+    }
+
+    #[test]
+    fn jiq_prefers_idle_computers_in_the_order_they_became_idle() {
+        let environment = environment();
+        let outstanding = &environment.outstanding;
+        let mut jiq = jiq(&environment);
+        add(&mut jiq, &[1, 2, 3]);
+        // New e-computers are idle
+        assert_eq!(jiq.select(), Some(1));
+        let first = busy(outstanding, 1, 1);
+        assert_eq!(jiq.select_excluding(&[2]), Some(3));
+        let third = busy(outstanding, 3, 1);
+        assert_eq!(jiq.select(), Some(2));
+        let second = busy(outstanding, 2, 1);
+
+        // Idle again only once nothing is in flight there
+        let mut also_third = busy(outstanding, 3, 1);
+        drop(third);
+        jiq.on_completion(3, ms(10));
+        drop(first);
+        jiq.on_rejected(1);
+        assert_eq!(jiq.select(), Some(1));
+        also_third.clear();
+        jiq.on_completion(3, ms(10));
+        assert_eq!(jiq.select(), Some(3));
+
+        // Nothing idle: any e-computer not excluded
+        let _busy = [busy(outstanding, 1, 1), busy(outstanding, 3, 1), second];
+        let counts = counts(&mut jiq, 300);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(jiq.select_excluding(&[1, 3]), Some(2));
+    }
+
+    #[test]
+    fn lor_breaks_ties_on_latency() {
+        let environment = environment();
+        let outstanding = &environment.outstanding;
+        let mut lor = lor(&environment);
+        add(&mut lor, &[1, 2, 3]);
+        lor.on_completion(1, ms(300));
+        lor.on_completion(2, ms(100));
+        lor.on_completion(3, ms(200));
+        assert_eq!(lor.select(), Some(2));
+
+        let two = busy(outstanding, 2, 1);
+        assert_eq!(lor.select(), Some(3));
+        let _three = busy(outstanding, 3, 2);
+        // 1 and 2 tie on one task in flight; 2 is faster
+        let _one = busy(outstanding, 1, 1);
+        assert_eq!(lor.select(), Some(2));
+        assert_eq!(lor.select_excluding(&[2]), Some(1));
+        drop(two);
+        assert_eq!(lor.select(), Some(2));
+    }
+
+    #[test]
+    fn failed_computers_are_passed_over_until_they_complete_a_task() {
+        let environment = environment();
+        let outstanding = &environment.outstanding;
+        let mut dispatchers: Vec<Box<dyn Dispatcher>> = vec![
+            Box::new(p2c(&environment, 2)),
+            Box::new(jsq(&environment)),
+            Box::new(jiq(&environment)),
+            Box::new(lor(&environment)),
+        ];
+        for dispatcher in &mut dispatchers {
+            add(dispatcher.as_mut(), &[1, 2]);
+            // Nothing in flight at 1, since it is down
+            let _busy = busy(outstanding, 2, 3);
+            dispatcher.on_failure(1, ms(5000));
+            assert_eq!(counts(dispatcher.as_mut(), 20), HashMap::from([(2, 20)]));
+            // Unless there is nowhere else to go
+            assert_eq!(dispatcher.select_excluding(&[2]), Some(1));
+            dispatcher.on_completion(1, ms(10));
+            assert_eq!(dispatcher.select(), Some(1));
+        }
+    }
+
+    #[test]
+    fn the_penalty_runs_out() {
+        let environment = environment();
+        let mut jsq = jsq(&environment);
+        add(&mut jsq, &[1, 2]);
+        let _busy = busy(&environment.outstanding, 2, 1);
+        jsq.on_failure(1, ms(5000));
+        environment.clock.set(ms(4999));
+        assert_eq!(jsq.select(), Some(2));
+        environment.clock.set(ms(5000));
+        assert_eq!(jsq.select(), Some(1));
+    }
+}
@@ -0,0 +1,384 @@
+// Routing algorithms shared by the e-routers, the benchmarks and the tests.
+// Every policy implements `Dispatcher`; the e-router only talks to the trait.
+use crate::clock::Clock;
+use crate::protocol::{Feedback, DEFAULT_FUNCTION};
+use crate::slo::Slos;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+mod closest;
+mod estimator;
+mod feedback;
+mod latency;
+mod load;
+mod outstanding;
+mod probing;
+mod rotation;
+mod slo;
+#[cfg(test)]
+mod test_util;
+mod weights;
+
+pub use closest::AlwaysClosest;
+pub use estimator::{
+    Bootstrap, Estimator, DEFAULT_ALPHA, DEFAULT_HALF_LIFE, DEFAULT_PERCENTILE, DEFAULT_WINDOW,
+};
+pub use feedback::{ExpectedDelay, LeastQueue};
+pub use latency::{LeastLatency, RandomProportional};
+pub use load::{
+    JoinIdleQueue, JoinShortestQueue, LeastOutstanding, PowerOfChoices, DEFAULT_CHOICES,
+};
+pub use outstanding::{Outstanding, OutstandingTable, Ticket};
+pub use probing::ProbingRoundRobin;
+pub use rotation::Rotation;
+pub use slo::SloAware;
+pub use weights::WeightTable;
+
+// An e-computer as seen by the e-router
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Destination {
+    pub port: u16,
+    pub hops: u32,
+}
+
+impl Destination {
+    pub fn new(port: u16, hops: u32) -> Self {
+        Destination { port, hops }
+    }
+}
+
+// Where dispatchers take the time and their random numbers from. The default
+// is the system clock and an unseeded generator; the simulator passes its
+// virtual clock and a seed so runs are reproducible. Load-aware policies read
+// the tasks in flight from `outstanding`, which whoever forwards the tasks
+// keeps up to date. Latency-driven policies estimate latency with `estimator`
+// and `bootstrap`, and SLO-aware ones route each function to its objective
+// in `slos`.
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    pub clock: Clock,
+    pub seed: Option<u64>,
+    pub outstanding: Outstanding,
+    pub estimator: Estimator,
+    pub bootstrap: Bootstrap,
+    pub slos: Slos,
+}
+
+impl Environment {
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    // An empty weight table estimating latency the way this environment says
+    pub fn weights(&self) -> WeightTable {
+        WeightTable::with_estimator(self.estimator, self.bootstrap, self.clock.clone())
+    }
+}
+
+// Builds a fresh dispatcher for the named function, e.g. one per function in
+// the e-table
+pub type DispatcherFactory = Box<dyn Fn(&str) -> Box<dyn Dispatcher> + Send + Sync>;
+
+pub trait Dispatcher: Send {
+    // Pick the e-computer for the next task, or None if there is nowhere to send it
+    fn select(&mut self) -> Option<u16> {
+        self.select_excluding(&[])
+    }
+
+    // Like `select`, but never one of `exclude`, e.g. the e-computers a
+    // retried task already failed on
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16>;
+
+    // Feed back the observed latency of a task that was sent to `port`
+    fn on_completion(&mut self, port: u16, latency: Duration);
+
+    // Feed back that a task sent to `port` failed. By default the failure
+    // counts as a completion taking `penalty`, so latency-driven policies
+    // steer away from the e-computer.
+    fn on_failure(&mut self, port: u16, penalty: Duration) {
+        self.on_completion(port, penalty);
+    }
+
+    // Feed back that `port` answered a task without running it, e.g. because
+    // its run queue was full. There is no latency to learn from, but policies
+    // counting tasks in flight need to know the task is gone.
+    fn on_rejected(&mut self, _port: u16) {}
+
+    // Feed back the load `port` reported with a response. Comes before the
+    // `on_completion` or `on_rejected` for the same response.
+    fn on_feedback(&mut self, _port: u16, _feedback: &Feedback) {}
+
+    fn add_destination(&mut self, destination: Destination);
+
+    fn remove_destination(&mut self, port: u16);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    LI,
+    RP,
+    RR,
+    AlwaysClosest,
+    Rotation,
+    // Power of d choices, `P2C[:D]`
+    PowerOfChoices { choices: usize },
+    JoinShortestQueue,
+    JoinIdleQueue,
+    LeastOutstanding,
+    LeastQueue,
+    ExpectedDelay,
+    Slo,
+}
+
+impl Algorithm {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Algorithm::LI => "LI",
+            Algorithm::RP => "RP",
+            Algorithm::RR => "RR",
+            Algorithm::AlwaysClosest => "AC",
+            Algorithm::Rotation => "ROT",
+            Algorithm::PowerOfChoices { .. } => "P2C",
+            Algorithm::JoinShortestQueue => "JSQ",
+            Algorithm::JoinIdleQueue => "JIQ",
+            Algorithm::LeastOutstanding => "LOR",
+            Algorithm::LeastQueue => "LQ",
+            Algorithm::ExpectedDelay => "ED",
+            Algorithm::Slo => "SLO",
+        }
+    }
+
+    // Build a dispatcher for this algorithm over the given e-computers
+    pub fn build(&self, destinations: Vec<Destination>) -> Box<dyn Dispatcher> {
+        self.build_in(&Environment::default(), DEFAULT_FUNCTION, destinations)
+    }
+
+    // Like `build`, but in `environment` and for `function`
+    pub fn build_in(
+        &self,
+        environment: &Environment,
+        function: &str,
+        destinations: Vec<Destination>,
+    ) -> Box<dyn Dispatcher> {
+        let clock = environment.clock.clone();
+        let outstanding = environment.outstanding.clone();
+        let mut dispatcher: Box<dyn Dispatcher> = match self {
+            Algorithm::LI => Box::new(LeastLatency::with_weights(environment.weights())),
+            Algorithm::RP => Box::new(RandomProportional::with_environment(
+                environment.weights(),
+                environment.rng(),
+            )),
+            Algorithm::RR => Box::new(ProbingRoundRobin::with_environment(
+                clock,
+                environment.weights(),
+            )),
+            Algorithm::AlwaysClosest => Box::new(AlwaysClosest::new()),
+            Algorithm::Rotation => Box::new(Rotation::new()),
+            Algorithm::PowerOfChoices { choices } => Box::new(PowerOfChoices::with_environment(
+                *choices,
+                outstanding,
+                clock,
+                environment.rng(),
+            )),
+            Algorithm::JoinShortestQueue => Box::new(JoinShortestQueue::with_environment(
+                outstanding,
+                clock,
+                environment.rng(),
+            )),
+            Algorithm::JoinIdleQueue => Box::new(JoinIdleQueue::with_environment(
+                outstanding,
+                clock,
+                environment.rng(),
+            )),
+            Algorithm::LeastOutstanding => Box::new(LeastOutstanding::with_environment(
+                outstanding,
+                clock,
+                environment.weights(),
+            )),
+            Algorithm::LeastQueue => Box::new(LeastQueue::with_environment(
+                outstanding,
+                clock,
+                environment.weights(),
+            )),
+            Algorithm::ExpectedDelay => Box::new(ExpectedDelay::with_environment(
+                outstanding,
+                clock,
+                environment.weights(),
+            )),
+            Algorithm::Slo => {
+                // Tail latency at the objective's own percentile
+                let slo = environment.slos.get(function);
+                let tails = match slo {
+                    Some(slo) => WeightTable::with_estimator(
+                        Estimator::WindowPercentile {
+                            percentile: slo.percentile,
+                            window: DEFAULT_WINDOW,
+                        },
+                        environment.bootstrap,
+                        clock.clone(),
+                    ),
+                    None => environment.weights(),
+                };
+                Box::new(SloAware::with_environment(slo, outstanding, tails, clock))
+            }
+        };
+        for destination in destinations {
+            dispatcher.add_destination(destination);
+        }
+        dispatcher
+    }
+
+    pub fn factory(&self) -> DispatcherFactory {
+        let algorithm = *self;
+        Box::new(move |_| algorithm.build(Vec::new()))
+    }
+
+    // Like `factory`, but every dispatcher it builds runs in `environment`.
+    // With a seed, each one gets its own stream derived from it.
+    pub fn factory_in(&self, environment: Environment) -> DispatcherFactory {
+        let algorithm = *self;
+        let built = AtomicU64::new(0);
+        Box::new(move |function| {
+            let n = built.fetch_add(1, Ordering::Relaxed);
+            let environment = Environment {
+                clock: environment.clock.clone(),
+                seed: environment.seed.map(|seed| seed.wrapping_add(n)),
+                outstanding: environment.outstanding.clone(),
+                estimator: environment.estimator,
+                bootstrap: environment.bootstrap,
+                slos: environment.slos.clone(),
+            };
+            algorithm.build_in(&environment, function, Vec::new())
+        })
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        if let Some(choices) = code.strip_prefix("P2C:") {
+            return match choices.parse() {
+                Ok(choices) if choices >= 1 => Ok(Algorithm::PowerOfChoices { choices }),
+                _ => Err(format!("Invalid number of choices in {}", code)),
+            };
+        }
+        match code {
+            "LI" => Ok(Algorithm::LI),
+            "RP" => Ok(Algorithm::RP),
+            // DRR was e_router_v3's separate deficit round robin, now the same as RR
+            "RR" | "DRR" => Ok(Algorithm::RR),
+            "AC" => Ok(Algorithm::AlwaysClosest),
+            "ROT" => Ok(Algorithm::Rotation),
+            "P2C" => Ok(Algorithm::PowerOfChoices {
+                choices: DEFAULT_CHOICES,
+            }),
+            "JSQ" => Ok(Algorithm::JoinShortestQueue),
+            "JIQ" => Ok(Algorithm::JoinIdleQueue),
+            "LOR" => Ok(Algorithm::LeastOutstanding),
+            "LQ" => Ok(Algorithm::LeastQueue),
+            "ED" => Ok(Algorithm::ExpectedDelay),
+            "SLO" => Ok(Algorithm::Slo),
+            _ => Err(format!(
+                "Invalid algorithm {}. Choose LI, RP, RR, AC, ROT, P2C[:D], JSQ, JIQ, LOR, LQ, ED or SLO.",
+                code
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::PowerOfChoices { choices } if *choices != DEFAULT_CHOICES => {
+                write!(f, "{}:{}", self.code(), choices)
+            }
+            _ => f.write_str(self.code()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODES: [&str; 12] = [
+        "LI", "RP", "RR", "AC", "ROT", "P2C", "JSQ", "JIQ", "LOR", "LQ", "ED", "SLO",
+    ];
+
+    fn every_algorithm() -> impl Iterator<Item = Algorithm> {
+        CODES.iter().map(|code| code.parse().unwrap())
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for (algorithm, code) in every_algorithm().zip(CODES) {
+            assert_eq!(algorithm.to_string(), code);
+        }
+        assert_eq!("DRR".parse::<Algorithm>(), Ok(Algorithm::RR));
+        assert_eq!(
+            "P2C:3".parse::<Algorithm>(),
+            Ok(Algorithm::PowerOfChoices { choices: 3 })
+        );
+        assert_eq!("P2C:3".parse::<Algorithm>().unwrap().to_string(), "P2C:3");
+        assert!("P2C:0".parse::<Algorithm>().is_err());
+        assert!("XYZ".parse::<Algorithm>().is_err());
+    }
+
+    // What every policy promises through the trait, whichever of the e-router,
+    // the simulator or a benchmark builds it
+    #[test]
+    fn every_algorithm_routes_to_its_destinations_only() {
+        for algorithm in every_algorithm() {
+            let mut dispatcher = algorithm.build(Vec::new());
+            assert_eq!(dispatcher.select(), None, "{} with nothing", algorithm);
+
+            let mut dispatcher = algorithm.build(vec![
+                Destination::new(1, 1),
+                Destination::new(2, 2),
+                Destination::new(3, 3),
+            ]);
+            for _ in 0..20 {
+                let port = dispatcher.select().unwrap();
+                assert!((1..=3).contains(&port), "{} picked {}", algorithm, port);
+                dispatcher.on_completion(port, Duration::from_millis(port as u64));
+            }
+            for _ in 0..20 {
+                let port = dispatcher.select_excluding(&[1, 2]);
+                assert_eq!(port, Some(3), "{} ignored the exclusions", algorithm);
+                dispatcher.on_completion(3, Duration::from_millis(3));
+            }
+            assert_eq!(dispatcher.select_excluding(&[1, 2, 3]), None);
+
+            dispatcher.remove_destination(3);
+            dispatcher.remove_destination(1);
+            for _ in 0..20 {
+                assert_eq!(dispatcher.select(), Some(2), "{} after removal", algorithm);
+                dispatcher.on_completion(2, Duration::from_millis(2));
+            }
+            dispatcher.add_destination(Destination::new(4, 1));
+            let mut seen = Vec::new();
+            for _ in 0..200 {
+                let port = dispatcher.select().unwrap();
+                assert!(port == 2 || port == 4, "{} picked {}", algorithm, port);
+                seen.push(port);
+                dispatcher.on_completion(port, Duration::from_millis(1));
+            }
+            // LQ and ED only learn from the load e-computers report, and none do here
+            if !matches!(algorithm, Algorithm::LeastQueue | Algorithm::ExpectedDelay) {
+                assert!(
+                    seen.contains(&4),
+                    "{} never tried the new e-computer",
+                    algorithm
+                );
+            }
+        }
+    }
+}
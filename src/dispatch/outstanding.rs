@@ -0,0 +1,139 @@
+use crate::clock::Clock;
+use crate::protocol::{ComputerLoad, Inspection, OutstandingRequest};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+struct Request {
+    task: String,
+    function: String,
+    port: u16,
+    started: Duration,
+}
+
+// The requests themselves, by when they started, and how many each
+// e-computer has. The counts are indexed by port, so policies scanning
+// thousands of e-computers per task don't hash every one.
+#[derive(Debug, Default)]
+pub struct OutstandingTable {
+    requests: BTreeMap<u64, Request>,
+    in_flight: Vec<usize>,
+    next: u64,
+}
+
+impl OutstandingTable {
+    pub fn in_flight(&self, port: u16) -> usize {
+        self.in_flight.get(port as usize).copied().unwrap_or(0)
+    }
+
+    // E-computers with something in flight
+    fn busy(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.in_flight.len())
+            .filter(|&port| self.in_flight[port] > 0)
+            .map(|port| port as u16)
+    }
+}
+
+// Tasks forwarded to e-computers that haven't completed, failed or been
+// rejected yet, across all functions. The e-router (or the simulator) records
+// every forwarding attempt; dispatchers built in the same `Environment` read
+// the counts to route on load.
+#[derive(Clone, Debug, Default)]
+pub struct Outstanding {
+    table: Arc<Mutex<OutstandingTable>>,
+    clock: Clock,
+}
+
+impl Outstanding {
+    pub fn new(clock: Clock) -> Self {
+        Outstanding {
+            table: Arc::default(),
+            clock,
+        }
+    }
+
+    // Record a task sent to `port`; it stays outstanding until the ticket is dropped
+    pub fn start(&self, task: &str, function: &str, port: u16) -> Ticket {
+        let started = self.clock.now();
+        let mut table = self.lock();
+        let id = table.next;
+        table.next += 1;
+        table.requests.insert(
+            id,
+            Request {
+                task: task.to_string(),
+                function: function.to_string(),
+                port,
+                started,
+            },
+        );
+        let slot = port as usize;
+        if table.in_flight.len() <= slot {
+            table.in_flight.resize(slot + 1, 0);
+        }
+        table.in_flight[slot] += 1;
+        Ticket {
+            outstanding: self.clone(),
+            id,
+        }
+    }
+
+    pub fn in_flight(&self, port: u16) -> usize {
+        self.lock().in_flight(port)
+    }
+
+    // Hold the table to read many counts at once
+    pub fn lock(&self) -> MutexGuard<'_, OutstandingTable> {
+        self.table.lock().unwrap()
+    }
+
+    // Counts for `ports` and for any other e-computer with something
+    // outstanding, plus every outstanding request
+    pub fn inspect(&self, ports: impl IntoIterator<Item = u16>) -> Inspection {
+        let now = self.clock.now();
+        let table = self.lock();
+        let ports: BTreeSet<u16> = ports.into_iter().chain(table.busy()).collect();
+        Inspection {
+            computers: ports
+                .into_iter()
+                .map(|port| ComputerLoad {
+                    port,
+                    in_flight: table.in_flight(port),
+                })
+                .collect(),
+            requests: table
+                .requests
+                .values()
+                .map(|request| OutstandingRequest {
+                    task: request.task.clone(),
+                    function: request.function.clone(),
+                    port: request.port,
+                    age_ms: now.saturating_sub(request.started).as_millis() as u64,
+                })
+                .collect(),
+            slos: Vec::new(),
+        }
+    }
+
+    fn finish(&self, id: u64) {
+        let mut table = self.lock();
+        if let Some(request) = table.requests.remove(&id) {
+            table.in_flight[request.port as usize] -= 1;
+        }
+    }
+}
+
+// One outstanding request. Dropping it, on any path, marks it finished; drop
+// it before feeding the outcome back so dispatchers see the new count.
+#[derive(Debug)]
+pub struct Ticket {
+    outstanding: Outstanding,
+    id: u64,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.outstanding.finish(self.id);
+    }
+}
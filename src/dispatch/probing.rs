@@ -0,0 +1,389 @@
+use super::{Destination, Dispatcher, WeightTable};
+use crate::clock::Clock;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+// An e-computer stays active while its weight is at most this many times the
+// lowest active weight
+const ACTIVE_RATIO: f64 = 2.0;
+// Time an inactive e-computer waits before it is probed, doubled after every
+// probe that doesn't bring it back and reset when it rejoins. The bounds are
+// from the paper source code.
+pub const MIN_STALE_PERIOD: Duration = Duration::from_secs(1);
+pub const MAX_STALE_PERIOD: Duration = Duration::from_secs(30);
+
+// Seconds ordered with `total_cmp`, so weights and deficits can key a BTreeSet
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Seconds(f64);
+
+impl Ord for Seconds {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Seconds {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for Seconds {}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    // Served round robin. The deficit is the work in seconds (sum of weights)
+    // sent to it so far; the lowest deficit goes next.
+    Active { deficit: f64 },
+    // Only sent a probe once the clock reaches `next_probe`
+    Inactive { next_probe: Duration },
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    state: State,
+    stale_period: Duration,
+}
+
+// RR: weighted round robin over an active set of e-computers whose smoothed
+// latency is within twice the lowest, as in the paper. Every task goes to the
+// active e-computer with the lowest deficit, whose deficit then grows by its
+// weight, so each gets tasks in inverse proportion to its latency. Inactive
+// e-computers, new ones included, get a single probe task when their stale
+// period runs out and rejoin at the lowest deficit if it comes back fast enough.
+pub struct ProbingRoundRobin {
+    entries: HashMap<u16, Entry>,
+    // Smoothed latency in seconds
+    weights: WeightTable,
+    // Active e-computers by deficit and by weight, ties broken by port
+    by_deficit: BTreeSet<(Seconds, u16)>,
+    by_weight: BTreeSet<(Seconds, u16)>,
+    // Inactive e-computers by the time of their next probe
+    probes: BTreeSet<(Duration, u16)>,
+    clock: Clock,
+}
+
+impl Default for ProbingRoundRobin {
+    fn default() -> Self {
+        ProbingRoundRobin::with_clock(Clock::system())
+    }
+}
+
+impl ProbingRoundRobin {
+    pub fn new() -> Self {
+        ProbingRoundRobin::default()
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        let weights =
+            WeightTable::with_estimator(Default::default(), Default::default(), clock.clone());
+        ProbingRoundRobin::with_environment(clock, weights)
+    }
+
+    pub fn with_environment(clock: Clock, weights: WeightTable) -> Self {
+        ProbingRoundRobin {
+            entries: HashMap::new(),
+            weights,
+            by_deficit: BTreeSet::new(),
+            by_weight: BTreeSet::new(),
+            probes: BTreeSet::new(),
+            clock,
+        }
+    }
+
+    // Send a probe to an inactive e-computer and back off until the next one,
+    // in case this one never comes back
+    fn probe(&mut self, port: u16, now: Duration) {
+        let entry = self.entries.get_mut(&port).unwrap();
+        if let State::Inactive { next_probe } = entry.state {
+            self.probes.remove(&(next_probe, port));
+        }
+        entry.stale_period = (2 * entry.stale_period).min(MAX_STALE_PERIOD);
+        let next_probe = now + entry.stale_period;
+        entry.state = State::Inactive { next_probe };
+        self.probes.insert((next_probe, port));
+    }
+
+    // Join the active set at the lowest deficit, so the e-computer neither
+    // gets a burst of tasks nor waits for the others to catch up
+    fn activate(&mut self, port: u16) {
+        let entry = self.entries.get_mut(&port).unwrap();
+        if let State::Inactive { next_probe } = entry.state {
+            self.probes.remove(&(next_probe, port));
+        }
+        let deficit = self
+            .by_deficit
+            .first()
+            .map_or(0.0, |(deficit, _)| deficit.0);
+        entry.state = State::Active { deficit };
+        entry.stale_period = MIN_STALE_PERIOD;
+        self.by_deficit.insert((Seconds(deficit), port));
+        self.by_weight
+            .insert((Seconds(self.weights.estimate(port)), port));
+    }
+
+    fn deactivate(&mut self, port: u16, now: Duration) {
+        let entry = self.entries.get_mut(&port).unwrap();
+        if let State::Active { deficit } = entry.state {
+            self.by_deficit.remove(&(Seconds(deficit), port));
+            self.by_weight
+                .remove(&(Seconds(self.weights.estimate(port)), port));
+        }
+        let next_probe = now + entry.stale_period;
+        entry.state = State::Inactive { next_probe };
+        self.probes.insert((next_probe, port));
+    }
+
+    // Drop the slowest active e-computers until all are within the ratio
+    fn evict_slow(&mut self, now: Duration) {
+        while let (Some(&(fastest, _)), Some(&(slowest, port))) =
+            (self.by_weight.first(), self.by_weight.last())
+        {
+            if slowest.0 <= ACTIVE_RATIO * fastest.0 {
+                break;
+            }
+            self.deactivate(port, now);
+        }
+    }
+}
+
+impl Dispatcher for ProbingRoundRobin {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.clock.now();
+        let due = self
+            .probes
+            .iter()
+            .take_while(|(at, _)| *at <= now)
+            .find(|(_, port)| !exclude.contains(port))
+            .map(|(_, port)| *port);
+        if let Some(port) = due {
+            self.probe(port, now);
+            return Some(port);
+        }
+
+        if let Some(&(old, port)) = self
+            .by_deficit
+            .iter()
+            .find(|(_, port)| !exclude.contains(port))
+        {
+            let deficit = old.0 + self.weights.estimate(port);
+            self.by_deficit.remove(&(old, port));
+            self.by_deficit.insert((Seconds(deficit), port));
+            self.entries.get_mut(&port).unwrap().state = State::Active { deficit };
+            return Some(port);
+        }
+
+        // Nothing active to send to, so probe early rather than fail the task
+        let (_, port) = *self
+            .probes
+            .iter()
+            .find(|(_, port)| !exclude.contains(port))?;
+        self.probe(port, now);
+        Some(port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        let now = self.clock.now();
+        let Some(entry) = self.entries.get(&port) else {
+            return;
+        };
+        match entry.state {
+            State::Active { .. } => {
+                self.by_weight
+                    .remove(&(Seconds(self.weights.estimate(port)), port));
+                self.weights.update(port, latency);
+                self.by_weight
+                    .insert((Seconds(self.weights.estimate(port)), port));
+            }
+            State::Inactive { .. } => {
+                // A probe measures the e-computer as it is now, so its old
+                // smoothed latency doesn't hold it back
+                let weight = latency.as_secs_f64();
+                self.weights.set(port, weight);
+                let fast_enough = self
+                    .by_weight
+                    .first()
+                    .is_none_or(|(fastest, _)| weight <= ACTIVE_RATIO * fastest.0);
+                if fast_enough {
+                    self.activate(port);
+                }
+            }
+        }
+        self.evict_slow(now);
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        let port = destination.port;
+        if self.entries.contains_key(&port) {
+            return;
+        }
+        // Nothing is known about a new e-computer, so it is probed right away
+        let next_probe = self.clock.now();
+        self.entries.insert(
+            port,
+            Entry {
+                state: State::Inactive { next_probe },
+                stale_period: MIN_STALE_PERIOD,
+            },
+        );
+        self.weights.insert(port);
+        self.probes.insert((next_probe, port));
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        let Some(entry) = self.entries.remove(&port) else {
+            return;
+        };
+        match entry.state {
+            State::Active { deficit } => {
+                self.by_deficit.remove(&(Seconds(deficit), port));
+                self.by_weight
+                    .remove(&(Seconds(self.weights.estimate(port)), port));
+            }
+            State::Inactive { next_probe } => {
+                self.probes.remove(&(next_probe, port));
+            }
+        }
+        self.weights.remove(port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::test_util::{counts, ms};
+
+    // A dispatcher on a virtual clock at zero with e-computers 1..=n
+    fn round_robin(n: u16) -> (ProbingRoundRobin, Clock) {
+        let clock = Clock::virtual_clock();
+        let mut rr = ProbingRoundRobin::with_clock(clock.clone());
+        for port in 1..=n {
+            rr.add_destination(Destination::new(port, 1));
+        }
+        (rr, clock)
+    }
+
+    // Probe every e-computer and answer each probe with the given latency
+    fn warm_up(rr: &mut ProbingRoundRobin, latencies: &[Duration]) {
+        for (i, latency) in latencies.iter().enumerate() {
+            assert_eq!(rr.select(), Some(i as u16 + 1));
+            rr.on_completion(i as u16 + 1, *latency);
+        }
+    }
+
+    #[test]
+    fn new_destinations_are_probed_first() {
+        let (mut rr, _) = round_robin(3);
+        assert_eq!(rr.select(), Some(1));
+        assert_eq!(rr.select(), Some(2));
+        assert_eq!(rr.select(), Some(3));
+    }
+
+    #[test]
+    fn nothing_active_probes_early_instead_of_failing() {
+        let (mut rr, _) = round_robin(2);
+        assert_eq!(rr.select(), Some(1));
+        assert_eq!(rr.select(), Some(2));
+        // Both probes are still out
+        assert_eq!(rr.select(), Some(1));
+    }
+
+    #[test]
+    fn lowest_deficit_goes_first() {
+        let (mut rr, _) = round_robin(2);
+        warm_up(&mut rr, &[ms(125), ms(250)]);
+        // Both join at deficit 0; ties go to the lower port
+        assert_eq!(rr.select(), Some(1));
+        assert_eq!(rr.select(), Some(2));
+        assert_eq!(rr.select(), Some(1));
+    }
+
+    #[test]
+    fn tasks_are_shared_in_inverse_proportion_to_latency() {
+        let (mut rr, _) = round_robin(3);
+        warm_up(&mut rr, &[ms(125), ms(250), ms(250)]);
+        let counts = counts(&mut rr, 40);
+        assert_eq!(counts[&1], 20);
+        assert_eq!(counts[&2], 10);
+        assert_eq!(counts[&3], 10);
+    }
+
+    #[test]
+    fn slow_destination_stays_out_of_the_active_set() {
+        let (mut rr, clock) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(300)]);
+        // The probe sent at 0 doubled the stale period to 2s
+        clock.set(ms(1999));
+        assert_eq!(counts(&mut rr, 10)[&1], 10);
+        clock.set(ms(2000));
+        assert_eq!(rr.select(), Some(2));
+        assert_eq!(rr.select(), Some(1));
+    }
+
+    #[test]
+    fn probes_back_off_exponentially_up_to_the_limit() {
+        let (mut rr, clock) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(300)]);
+        let mut now = Duration::ZERO;
+        let mut gaps = Vec::new();
+        for _ in 0..6 {
+            let next = (now.as_millis() as u64..)
+                .step_by(500)
+                .map(ms)
+                .find(|at| {
+                    clock.set(*at);
+                    rr.select() == Some(2)
+                })
+                .unwrap();
+            rr.on_completion(2, ms(300));
+            gaps.push((next - now).as_secs());
+            now = next;
+        }
+        assert_eq!(gaps, vec![2, 4, 8, 16, 30, 30]);
+    }
+
+    #[test]
+    fn recovered_destination_rejoins_at_the_lowest_deficit() {
+        let (mut rr, clock) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(300)]);
+        counts(&mut rr, 50);
+        clock.set(ms(2000));
+        assert_eq!(rr.select(), Some(2));
+        rr.on_completion(2, ms(150));
+        // No burst to catch up with the 50 tasks port 1 got meanwhile
+        let counts = counts(&mut rr, 3);
+        assert_eq!(counts[&1], 2);
+        assert_eq!(counts[&2], 1);
+    }
+
+    #[test]
+    fn destination_that_slows_down_is_evicted_and_probed_after_the_minimum_period() {
+        let (mut rr, clock) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(100)]);
+        // The smoothed weight jumps to 0.345s, over twice the fastest
+        rr.on_completion(2, Duration::from_secs(5));
+        assert_eq!(counts(&mut rr, 10)[&1], 10);
+        clock.set(MIN_STALE_PERIOD);
+        assert_eq!(rr.select(), Some(2));
+    }
+
+    #[test]
+    fn excluded_destinations_are_skipped() {
+        let (mut rr, _) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(100)]);
+        assert_eq!(rr.select_excluding(&[1]), Some(2));
+        assert_eq!(rr.select_excluding(&[1]), Some(2));
+        assert_eq!(rr.select_excluding(&[1, 2]), None);
+    }
+
+    #[test]
+    fn removed_destination_is_never_selected() {
+        let (mut rr, _) = round_robin(2);
+        warm_up(&mut rr, &[ms(100), ms(100)]);
+        rr.remove_destination(1);
+        assert_eq!(counts(&mut rr, 5)[&2], 5);
+        rr.remove_destination(2);
+        assert_eq!(rr.select(), None);
+    }
+}
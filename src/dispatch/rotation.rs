@@ -0,0 +1,40 @@
+use super::{Destination, Dispatcher};
+use std::time::Duration;
+
+// Plain round robin over the e-computers, ignoring latency.
+// This is what the in-process e-table benchmarks use.
+#[derive(Default)]
+pub struct Rotation {
+    destinations: Vec<Destination>,
+    next: usize,
+}
+
+impl Rotation {
+    pub fn new() -> Self {
+        Rotation::default()
+    }
+}
+
+impl Dispatcher for Rotation {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        // Skip over excluded e-computers, at most once around
+        for _ in 0..self.destinations.len() {
+            let selected = self.destinations[self.next % self.destinations.len()].port;
+            self.next = (self.next + 1) % self.destinations.len();
+            if !exclude.contains(&selected) {
+                return Some(selected);
+            }
+        }
+        None
+    }
+
+    fn on_completion(&mut self, _: u16, _: Duration) {}
+
+    fn add_destination(&mut self, destination: Destination) {
+        self.destinations.push(destination);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.destinations.retain(|d| d.port != port);
+    }
+}
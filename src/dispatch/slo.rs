@@ -0,0 +1,248 @@
+use super::feedback::Reports;
+use super::probing::MIN_STALE_PERIOD;
+use super::{Destination, Dispatcher, Outstanding, OutstandingTable, WeightTable};
+use crate::clock::Clock;
+use crate::protocol::Feedback;
+use crate::slo::Slo;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// SLO: send to the closest e-computer (by hops) predicted to answer within the
+// function's latency target, spilling over to farther ones only when every
+// closer one is predicted to miss it, and to the one predicted fastest when
+// they all are. Without a target every e-computer meets it, so tasks go to
+// the closest.
+// The prediction is the latency at the objective's percentile over the last
+// tasks, less the time they spent in the run queue, once more for every round
+// of tasks ahead of this one at the e-computer (ours in flight plus whatever
+// else it last reported, over its workers).
+pub struct SloAware {
+    slo: Option<Slo>,
+    // Closest first, ties broken by port
+    destinations: Vec<Destination>,
+    // Tail latency without queueing in seconds
+    tails: WeightTable,
+    reports: Reports,
+    // Queueing time of the last response from each e-computer, to take off
+    // its latency
+    queued: HashMap<u16, Duration>,
+    // When each e-computer was last sent a task
+    last_sent: HashMap<u16, Duration>,
+    outstanding: Outstanding,
+    clock: Clock,
+}
+
+impl Default for SloAware {
+    fn default() -> Self {
+        SloAware::with_environment(
+            None,
+            Outstanding::default(),
+            WeightTable::new(),
+            Clock::system(),
+        )
+    }
+}
+
+impl SloAware {
+    pub fn new() -> Self {
+        SloAware::default()
+    }
+
+    // `tails` is the empty table the tail latencies are estimated in
+    pub fn with_environment(
+        slo: Option<Slo>,
+        outstanding: Outstanding,
+        tails: WeightTable,
+        clock: Clock,
+    ) -> Self {
+        SloAware {
+            slo,
+            destinations: Vec::new(),
+            tails,
+            reports: Reports::default(),
+            queued: HashMap::new(),
+            last_sent: HashMap::new(),
+            outstanding,
+            clock,
+        }
+    }
+
+    // Predicted latency in seconds of a task sent to `port` now, and the
+    // tasks already there
+    fn predict(&self, table: &OutstandingTable, port: u16) -> (f64, usize) {
+        let report = self.reports.get(port);
+        let backlog = report.backlog(table.in_flight(port));
+        let rounds = 1 + backlog / report.workers;
+        (self.tails.estimate(port) * rounds as f64, backlog)
+    }
+}
+
+impl Dispatcher for SloAware {
+    fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+        let now = self.clock.now();
+        let target = self
+            .slo
+            .map_or(f64::INFINITY, |slo| slo.target.as_secs_f64());
+        let table = self.outstanding.lock();
+        let mut chosen = None;
+        let mut fastest: Option<(u16, f64)> = None;
+        for destination in &self.destinations {
+            let port = destination.port;
+            if exclude.contains(&port) {
+                continue;
+            }
+            let (predicted, backlog) = self.predict(&table, port);
+            if predicted <= target {
+                chosen = Some(port);
+                break;
+            }
+            // An idle e-computer only misses on its past tasks, which stop
+            // coming once it misses. Now and then it gets one to find out
+            // whether it still does.
+            let last_sent = self.last_sent.get(&port).copied().unwrap_or_default();
+            if backlog == 0 && now.saturating_sub(last_sent) >= MIN_STALE_PERIOD {
+                chosen = Some(port);
+                break;
+            }
+            if fastest.is_none_or(|(_, best)| predicted < best) {
+                fastest = Some((port, predicted));
+            }
+        }
+        drop(table);
+        let port = chosen.or(fastest.map(|(port, _)| port))?;
+        self.last_sent.insert(port, now);
+        Some(port)
+    }
+
+    fn on_completion(&mut self, port: u16, latency: Duration) {
+        let queued = self.queued.remove(&port).unwrap_or_default();
+        self.tails.update(port, latency.saturating_sub(queued));
+    }
+
+    fn on_feedback(&mut self, port: u16, feedback: &Feedback) {
+        self.reports.record(port, feedback, &self.outstanding);
+        if let Some(queue) = feedback.queue_time() {
+            self.queued.insert(port, queue);
+        }
+    }
+
+    fn add_destination(&mut self, destination: Destination) {
+        let key = |d: &Destination| (d.hops, d.port);
+        let at = self
+            .destinations
+            .partition_point(|d| key(d) < key(&destination));
+        self.tails.insert(destination.port);
+        self.reports.insert(destination.port);
+        self.destinations.insert(at, destination);
+    }
+
+    fn remove_destination(&mut self, port: u16) {
+        self.destinations.retain(|d| d.port != port);
+        self.tails.remove(port);
+        self.reports.remove(port);
+        self.queued.remove(&port);
+        self.last_sent.remove(&port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::test_util::{environment, ms};
+
+    // A dispatcher on a virtual clock at zero with e-computer `port` at
+    // `port` hops for ports 1..=3, where every sample replaces the tail
+    // latency
+    fn slo_aware(target: Option<u64>) -> (SloAware, Clock, Outstanding) {
+        let environment = environment();
+        let (clock, outstanding) = (environment.clock.clone(), environment.outstanding.clone());
+        let tails = environment.weights();
+        let slo = target.map(|target| Slo {
+            percentile: 95.0,
+            target: ms(target),
+        });
+        let mut dispatcher =
+            SloAware::with_environment(slo, outstanding.clone(), tails, clock.clone());
+        for port in [3, 1, 2] {
+            dispatcher.add_destination(Destination::new(port, port as u32));
+        }
+        (dispatcher, clock, outstanding)
+    }
+
+    fn answer(dispatcher: &mut SloAware, latencies: [u64; 3]) {
+        for (port, latency) in (1..).zip(latencies) {
+            dispatcher.on_completion(port, ms(latency));
+        }
+    }
+
+    #[test]
+    fn the_closest_computer_meeting_the_target_wins() {
+        let (mut slo, _, _) = slo_aware(Some(300));
+        assert_eq!(slo.select(), Some(1));
+        answer(&mut slo, [200, 100, 50]);
+        assert_eq!(slo.select(), Some(1));
+
+        // Time spent queueing says nothing about the e-computer itself
+        slo.on_feedback(
+            1,
+            &Feedback {
+                queue_len: 0,
+                active_workers: 0,
+                workers: 1,
+                service_ms: Some(150.0),
+                queue_ms: Some(300.0),
+            },
+        );
+        slo.on_completion(1, ms(450));
+        assert_eq!(slo.select(), Some(1));
+
+        // Without a target the closest always meets it
+        let (mut closest, _, _) = slo_aware(None);
+        answer(&mut closest, [900, 100, 50]);
+        assert_eq!(closest.select(), Some(1));
+    }
+
+    #[test]
+    fn tasks_spill_over_to_farther_computers() {
+        let (mut slo, _, outstanding) = slo_aware(Some(300));
+        answer(&mut slo, [200, 250, 50]);
+        // A second task at 1 has to wait a round: 400 ms
+        let first = outstanding.start("task-1", "f", 1);
+        assert_eq!(slo.select(), Some(2));
+        let _second = outstanding.start("task-2", "f", 2);
+        assert_eq!(slo.select(), Some(3));
+        drop(first);
+        assert_eq!(slo.select(), Some(1));
+
+        // Or it is too slow on its own
+        slo.on_completion(1, ms(350));
+        assert_eq!(slo.select_excluding(&[2]), Some(3));
+    }
+
+    #[test]
+    fn the_fastest_computer_takes_tasks_when_all_miss() {
+        let (mut slo, _, _) = slo_aware(Some(300));
+        answer(&mut slo, [500, 400, 450]);
+        assert_eq!(slo.select(), Some(2));
+        assert_eq!(slo.select_excluding(&[2]), Some(3));
+        assert_eq!(slo.select_excluding(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn idle_computers_that_miss_are_tried_again() {
+        let (mut slo, clock, outstanding) = slo_aware(Some(300));
+        answer(&mut slo, [500, 100, 50]);
+        assert_eq!(slo.select(), Some(2));
+        clock.set(MIN_STALE_PERIOD - ms(1));
+        assert_eq!(slo.select(), Some(2));
+        clock.set(MIN_STALE_PERIOD);
+        assert_eq!(slo.select(), Some(1));
+        // Once per period
+        assert_eq!(slo.select(), Some(2));
+
+        // Only when it is idle, since a busy one will answer anyway
+        let _busy = outstanding.start("task-1", "f", 1);
+        clock.set(MIN_STALE_PERIOD * 3);
+        assert_eq!(slo.select(), Some(2));
+    }
+}
@@ -0,0 +1,44 @@
+// Fixtures shared by the dispatcher tests
+use super::{Destination, Dispatcher, Environment, Estimator, Outstanding, Ticket};
+use crate::clock::Clock;
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+// An environment on a virtual clock at zero, where every sample replaces the
+// estimate
+pub fn environment() -> Environment {
+    let clock = Clock::virtual_clock();
+    Environment {
+        outstanding: Outstanding::new(clock.clone()),
+        clock,
+        estimator: Estimator::WindowMean { window: 1 },
+        ..Environment::default()
+    }
+}
+
+// Add e-computers on `ports`, one hop away
+pub fn add(dispatcher: &mut (impl Dispatcher + ?Sized), ports: &[u16]) {
+    for &port in ports {
+        dispatcher.add_destination(Destination::new(port, 1));
+    }
+}
+
+// Put `count` tasks in flight at `port` for as long as the tickets live
+pub fn busy(outstanding: &Outstanding, port: u16, count: usize) -> Vec<Ticket> {
+    (0..count)
+        .map(|i| outstanding.start(&format!("task-{}", i), "f", port))
+        .collect()
+}
+
+// Tasks sent to each e-computer out of `tasks` selections
+pub fn counts(dispatcher: &mut (impl Dispatcher + ?Sized), tasks: usize) -> HashMap<u16, usize> {
+    let mut counts = HashMap::new();
+    for _ in 0..tasks {
+        *counts.entry(dispatcher.select().unwrap()).or_default() += 1;
+    }
+    counts
+}
@@ -0,0 +1,150 @@
+use super::estimator::{Bootstrap, Estimator, Samples};
+use crate::clock::Clock;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Estimated latency (in seconds) per e-computer, from an `Estimator` over its
+// samples. E-computers without samples are unknown; `estimate` and `iter`
+// stand in for them according to the `Bootstrap` policy.
+// Entries are kept in insertion order so policies can scan them without a
+// lookup per e-computer, which matters with thousands of them in `sim`.
+#[derive(Clone, Debug, Default)]
+pub struct WeightTable {
+    weights: Vec<(u16, Samples)>,
+    index: HashMap<u16, usize>,
+    estimator: Estimator,
+    bootstrap: Bootstrap,
+    clock: Clock,
+    // Sum and number of the known estimates, for `Bootstrap::Average`
+    known_sum: f64,
+    known: usize,
+}
+
+impl WeightTable {
+    pub fn new() -> Self {
+        WeightTable::default()
+    }
+
+    pub fn with_estimator(estimator: Estimator, bootstrap: Bootstrap, clock: Clock) -> Self {
+        WeightTable {
+            estimator,
+            bootstrap,
+            clock,
+            ..WeightTable::default()
+        }
+    }
+
+    pub fn insert(&mut self, port: u16) {
+        if !self.index.contains_key(&port) {
+            self.index.insert(port, self.weights.len());
+            self.weights.push((port, Samples::default()));
+        }
+    }
+
+    pub fn remove(&mut self, port: u16) {
+        if let Some(i) = self.index.remove(&port) {
+            let (_, samples) = self.weights.remove(i);
+            self.forget(samples.value());
+            for (j, (p, _)) in self.weights.iter().enumerate().skip(i) {
+                self.index.insert(*p, j);
+            }
+        }
+    }
+
+    // The estimate from samples, or None if there are none yet
+    pub fn get(&self, port: u16) -> Option<f64> {
+        let &i = self.index.get(&port)?;
+        self.weights[i].1.value()
+    }
+
+    // The estimate, with unknown e-computers standing in per the bootstrap policy
+    pub fn estimate(&self, port: u16) -> f64 {
+        self.get(port).unwrap_or_else(|| self.unknown())
+    }
+
+    // (port, estimate) pairs in the order the ports were inserted
+    pub fn iter(&self) -> impl Iterator<Item = (u16, f64)> + '_ {
+        let unknown = self.unknown();
+        self.weights
+            .iter()
+            .map(move |(port, samples)| (*port, samples.value().unwrap_or(unknown)))
+    }
+
+    // Add a latency sample
+    pub fn update(&mut self, port: u16, latency: Duration) {
+        let now = self.clock.now();
+        let estimator = self.estimator;
+        self.change(port, |samples| {
+            estimator.update(samples, latency.as_secs_f64(), now)
+        });
+    }
+
+    // Throw the samples away and start again from `weight` seconds
+    pub fn set(&mut self, port: u16, weight: f64) {
+        let now = self.clock.now();
+        let estimator = self.estimator;
+        self.change(port, |samples| estimator.reset(samples, weight, now));
+    }
+
+    fn change(&mut self, port: u16, change: impl FnOnce(&mut Samples)) {
+        let Some(&i) = self.index.get(&port) else {
+            return;
+        };
+        let before = self.weights[i].1.value();
+        change(&mut self.weights[i].1);
+        let after = self.weights[i].1.value();
+        self.forget(before);
+        if let Some(after) = after {
+            self.known_sum += after;
+            self.known += 1;
+        }
+    }
+
+    fn forget(&mut self, value: Option<f64>) {
+        if let Some(value) = value {
+            self.known_sum -= value;
+            self.known -= 1;
+        }
+    }
+
+    fn unknown(&self) -> f64 {
+        match self.bootstrap {
+            Bootstrap::Optimistic => 0.0,
+            Bootstrap::Average if self.known == 0 => 0.0,
+            Bootstrap::Average => (self.known_sum / self.known as f64).max(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average_bootstrap_follows_the_known_estimates() {
+        let mut weights = WeightTable::with_estimator(
+            Estimator::WindowMean { window: 1 },
+            Bootstrap::Average,
+            Clock::virtual_clock(),
+        );
+        for port in 1..=3 {
+            weights.insert(port);
+        }
+        assert_eq!(weights.estimate(3), 0.0);
+        weights.update(1, Duration::from_millis(100));
+        assert_eq!(weights.get(3), None);
+        assert_eq!(weights.estimate(3), 0.1);
+        weights.update(2, Duration::from_millis(300));
+        assert!((weights.estimate(3) - 0.2).abs() < 1e-9);
+        // A new sample replaces the old one in the average
+        weights.update(2, Duration::from_millis(500));
+        assert!((weights.estimate(3) - 0.3).abs() < 1e-9);
+        weights.set(1, 0.3);
+        assert!((weights.estimate(3) - 0.4).abs() < 1e-9);
+        weights.remove(2);
+        assert!((weights.estimate(3) - 0.3).abs() < 1e-9);
+        weights.remove(1);
+        assert_eq!(weights.estimate(3), 0.0);
+        assert_eq!(weights.iter().collect::<Vec<_>>(), vec![(3, 0.0)]);
+    }
+}
@@ -0,0 +1,111 @@
+// In-process e-table benchmark shared by `src/main.rs` and `src/bin/rpi.rs`:
+// measures how many forwarding decisions per second the e-router can make.
+use crate::dispatch::{Algorithm, Destination, Dispatcher};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Lambda function name -> dispatcher over the e-computers hosting it
+type ETable = HashMap<String, Box<dyn Dispatcher>>;
+
+pub struct ERouter {
+    algorithm: Algorithm,
+    e_table: Arc<Mutex<ETable>>, // e-table storing lambda functions and their e-computers
+    forward_count: Arc<Mutex<u64>>, // Count of forwards
+}
+
+impl ERouter {
+    // Create a new ERouter
+    pub fn new(algorithm: Algorithm) -> Self {
+        ERouter {
+            algorithm,
+            e_table: Arc::new(Mutex::new(HashMap::new())),
+            forward_count: Arc::new(Mutex::new(0)), // Initialize forward count
+        }
+    }
+
+    // Add an e-computer for a lambda function to the e-table
+    pub fn add_entry(&self, function_name: &str, e_computer: Destination) {
+        let mut table = self.e_table.lock().unwrap();
+        table
+            .entry(function_name.to_string())
+            .or_insert_with(|| self.algorithm.build(Vec::new()))
+            .add_destination(e_computer);
+    }
+
+    // Select a destination e-computer for a lambda function
+    pub fn select_destination(&self, function_name: &str) -> Option<u16> {
+        let mut table = self.e_table.lock().unwrap();
+        table.get_mut(function_name)?.select()
+    }
+
+    // Handle a request from a client
+    pub fn handle_request(&self, function_name: &str) {
+        if self.select_destination(function_name).is_some() {
+            let mut count = self.forward_count.lock().unwrap();
+            *count += 1;
+        }
+    }
+
+    // Function to report processing rate every second
+    pub fn report_processing_rate(&self) {
+        let forward_count_clone = Arc::clone(&self.forward_count);
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let mut count = forward_count_clone.lock().unwrap();
+                println!("Processing rate: {} forwards per second", *count);
+                *count = 0; // Reset count after reporting
+            }
+        });
+    }
+}
+
+// Simulate client behavior sending requests
+pub fn client_simulation(e_router: Arc<ERouter>, function_names: Vec<String>) {
+    loop {
+        let function_name = &function_names[rand::thread_rng().gen_range(0..function_names.len())];
+        e_router.handle_request(function_name);
+    }
+}
+
+// Fill the e-table with `entries` rows spread over `functions` lambda functions and
+// `computers` e-computers, then hammer it from `clients` threads forever
+pub fn run(
+    algorithm: Algorithm,
+    entries: usize,
+    functions: usize,
+    computers: usize,
+    clients: usize,
+) {
+    let e_router = Arc::new(ERouter::new(algorithm));
+
+    for i in 0..entries {
+        let lambda_function = format!("lambda_{}", i % functions);
+        let e_computer = Destination::new((i % computers) as u16, 1);
+        e_router.add_entry(&lambda_function, e_computer);
+    }
+
+    // Start reporting processing rate
+    e_router.report_processing_rate();
+
+    // Start client threads with random lambda requests
+    let mut handles = vec![];
+    let function_names: Vec<String> = (0..functions).map(|i| format!("lambda_{}", i)).collect(); // Collect all unique lambda functions
+
+    for _ in 0..clients {
+        let router_clone = Arc::clone(&e_router);
+        let function_names_clone = function_names.clone();
+        handles.push(thread::spawn(move || {
+            client_simulation(router_clone, function_names_clone)
+        }));
+    }
+
+    // Wait for all client threads to finish (in practice they run indefinitely)
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
@@ -0,0 +1,347 @@
+// One-command experiment runs. A scenario (TOML or JSON) describes the
+// e-computers, the e-router and the load; for every client count (or arrival
+// rate) in the sweep we start fresh e-computer and e-router processes on
+// ephemeral ports, wire them together, drive them with the load generator,
+// write the latency log and tear everything down again.
+use crate::loadgen::{self, Arrivals, LoadConfig};
+use crate::protocol::DEFAULT_FUNCTION;
+use crate::stats::{self, CSV_HEADER, DEFAULT_RESAMPLES};
+use crate::workload::SizeDistribution;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComputerScenario {
+    pub speed: u64,
+    #[serde(default)]
+    pub hops: u32,
+    // Pooled connections from the e-router, default of the e-router if unset
+    pub pool: Option<usize>,
+    // Functions hosted, all of them if empty
+    #[serde(default)]
+    pub functions: Vec<String>,
+    // Extra e_computer arguments, e.g. ["--workers", "4", "--service", "exp"]
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouterScenario {
+    // LI, RP, RR, AC, ROT, P2C, JSQ, JIQ, LOR, LQ, ED
+    pub algorithm: String,
+    // Extra e_router arguments, e.g. ["--max-inflight", "16"]
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadMode {
+    Closed,
+    Open,
+}
+
+fn default_mode() -> LoadMode {
+    LoadMode::Closed
+}
+
+fn default_tasks() -> usize {
+    100
+}
+
+fn default_size() -> String {
+    "5000".to_string()
+}
+
+fn default_function() -> String {
+    DEFAULT_FUNCTION.to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoadScenario {
+    #[serde(default = "default_mode")]
+    pub mode: LoadMode,
+    // Closed loop: client counts to sweep, each sending `tasks` tasks
+    #[serde(default)]
+    pub clients: Vec<usize>,
+    // Open loop: arrival rates (tasks per second) to sweep, `tasks` tasks each
+    #[serde(default)]
+    pub rates: Vec<f64>,
+    #[serde(default = "default_tasks")]
+    pub tasks: usize,
+    #[serde(default = "default_size")]
+    pub size: String,
+    #[serde(default = "default_function")]
+    pub function: String,
+    pub seed: Option<u64>,
+    // Deadline of each task in milliseconds, none if unset
+    pub timeout_ms: Option<u64>,
+}
+
+fn default_output_dir() -> String {
+    "results".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub computers: Vec<ComputerScenario>,
+    pub router: RouterScenario,
+    pub load: LoadScenario,
+    #[serde(default = "default_output_dir")]
+    pub output_dir: String,
+}
+
+impl Scenario {
+    // Read a scenario, as TOML if the file ends in `.toml` and as JSON otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(&path)?;
+        let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+        let scenario: Scenario = if path.as_ref().extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text).map_err(|e| invalid(e.to_string()))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?
+        };
+        scenario.check().map_err(invalid)?;
+        Ok(scenario)
+    }
+
+    // Catch what deserializing lets through but the load generator can't run
+    fn check(&self) -> Result<(), String> {
+        if let Some(rate) = self
+            .load
+            .rates
+            .iter()
+            .find(|r| !(**r > 0.0 && r.is_finite()))
+        {
+            return Err(format!("Arrival rate {} must be a positive number", rate));
+        }
+        Ok(())
+    }
+}
+
+// A spawned process that is killed when dropped, so nothing outlives the run
+struct Process {
+    name: String,
+    child: Child,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Sibling binaries live next to the running e_experiment executable
+fn binary(name: &str) -> io::Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    Ok(exe.with_file_name(format!("{}{}", name, std::env::consts::EXE_SUFFIX)))
+}
+
+// Start a process and wait for it to announce `listening on port N`. Its
+// output is copied to `log` for the rest of its life, and its errors written
+// there directly.
+fn spawn(name: &str, binary: &Path, args: &[String], log: &Path) -> io::Result<(Process, u16)> {
+    let mut log_file = File::create(log)?;
+    let mut child = Command::new(binary)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(log_file.try_clone()?)
+        .spawn()?;
+    let stdout = child.stdout.take().unwrap();
+    let process = Process {
+        name: name.to_string(),
+        child,
+    };
+
+    let mut lines = BufReader::new(stdout);
+    let mut line = String::new();
+    let port = loop {
+        line.clear();
+        if lines.read_line(&mut line)? == 0 {
+            return Err(io::Error::other(format!(
+                "{} exited before announcing its port, see {}",
+                process.name,
+                log.display()
+            )));
+        }
+        log_file.write_all(line.as_bytes())?;
+        if let Some((_, port)) = line.trim().rsplit_once("listening on port ") {
+            break port
+                .parse()
+                .map_err(|_| io::Error::other(format!("Bad port line from {}", name)))?;
+        }
+    };
+    drain(lines, log_file);
+    Ok((process, port))
+}
+
+fn drain(mut lines: BufReader<ChildStdout>, mut log_file: File) {
+    thread::spawn(move || io::copy(&mut lines, &mut log_file));
+}
+
+// One point of the sweep: a client count or an arrival rate
+#[derive(Debug, Clone, Copy)]
+enum Point {
+    Clients(usize),
+    Rate(f64),
+}
+
+impl Point {
+    fn label(&self) -> String {
+        match self {
+            Point::Clients(clients) => clients.to_string(),
+            Point::Rate(rate) => format!("poisson-{}", rate),
+        }
+    }
+}
+
+pub fn run(scenario: &Scenario) -> io::Result<()> {
+    let sizes: SizeDistribution = scenario
+        .load
+        .size
+        .parse()
+        .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let points: Vec<Point> = match scenario.load.mode {
+        LoadMode::Closed => scenario
+            .load
+            .clients
+            .iter()
+            .map(|c| Point::Clients(*c))
+            .collect(),
+        LoadMode::Open => scenario
+            .load
+            .rates
+            .iter()
+            .map(|r| Point::Rate(*r))
+            .collect(),
+    };
+    if points.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Scenario has no client counts or arrival rates to run",
+        ));
+    }
+
+    let output_dir = Path::new(&scenario.output_dir);
+    fs::create_dir_all(output_dir)?;
+    let algorithm = scenario.router.algorithm.to_lowercase();
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut summary_csv = String::from(CSV_HEADER);
+    summary_csv.push('\n');
+
+    for point in points {
+        let label = format!("{}-{}", algorithm, point.label());
+        println!("=== Running {}", label);
+
+        let mut processes = Vec::new();
+        let mut specs = Vec::new();
+        for (i, computer) in scenario.computers.iter().enumerate() {
+            let mut args = vec![computer.speed.to_string()];
+            args.extend(computer.args.iter().cloned());
+            let log = output_dir.join(format!("computer-{}-{}.log", i, label));
+            let (process, port) = spawn(
+                &format!("e_computer {}", i),
+                &binary("e_computer")?,
+                &args,
+                &log,
+            )?;
+            println!(
+                "e_computer {} (speed {}) on port {}",
+                i, computer.speed, port
+            );
+            processes.push(process);
+
+            let mut spec = format!("{}:{}", port, computer.hops);
+            if let Some(pool) = computer.pool {
+                spec.push_str(&format!(":{}", pool));
+            }
+            if !computer.functions.is_empty() {
+                spec.push_str(&format!("@{}", computer.functions.join(",")));
+            }
+            specs.push(spec);
+        }
+
+        let mut args = vec![scenario.router.algorithm.clone()];
+        args.extend(specs);
+        args.extend(scenario.router.args.iter().cloned());
+        let log = output_dir.join(format!("router-{}.log", label));
+        let (router, router_port) = spawn("e_router", &binary("e_router")?, &args, &log)?;
+        println!(
+            "e_router ({}) on port {}",
+            scenario.router.algorithm, router_port
+        );
+        processes.push(router);
+
+        let arrivals = match point {
+            Point::Clients(clients) => Arrivals::Closed {
+                clients,
+                tasks_per_client: scenario.load.tasks,
+            },
+            Point::Rate(rate) => Arrivals::Poisson {
+                rate,
+                tasks: scenario.load.tasks,
+            },
+        };
+        let outcome = runtime.block_on(loadgen::run(LoadConfig {
+            router_port,
+            arrivals,
+            sizes,
+            function: scenario.load.function.clone(),
+            seed: scenario.load.seed,
+            timeout: scenario.load.timeout_ms.map(Duration::from_millis),
+        }));
+
+        // Tear down before the next point so runs don't share state
+        drop(processes);
+
+        let log = output_dir.join(format!("process-times-{}.log", label));
+        loadgen::write_log(&log, &outcome.latencies)?;
+        println!(
+            "{} tasks completed, {} failed in {:.2?}. Latencies written to {}",
+            outcome.latencies.len(),
+            outcome.failures,
+            outcome.elapsed,
+            log.display()
+        );
+        for (kind, count) in &outcome.failure_kinds {
+            println!("  {}: {}", kind, count);
+        }
+        if let Some(summary) = stats::summarize(&label, &outcome.latencies, DEFAULT_RESAMPLES, 0) {
+            println!("{}", summary.to_text());
+            summary_csv.push_str(&summary.to_csv());
+            summary_csv.push('\n');
+        }
+    }
+
+    let summary_path = output_dir.join(format!("summary-{}.csv", algorithm));
+    fs::write(&summary_path, summary_csv)?;
+    println!("Summary written to {}", summary_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenarios_reject_rates_the_load_generator_cant_run() {
+        let scenario = |rates: &str| -> Scenario {
+            toml::from_str(&format!(
+                "computers = [{{ speed = 100000 }}]\nrouter = {{ algorithm = \"LI\" }}\nload = {{ mode = \"open\", rates = {} }}",
+                rates
+            ))
+            .unwrap()
+        };
+        assert!(scenario("[5.0, 20.5]").check().is_ok());
+        assert!(Scenario::load("scenarios/exp-2.toml").is_ok());
+        for rates in ["[5.0, 0.0]", "[-1.0]", "[inf]", "[nan]"] {
+            assert!(scenario(rates).check().is_err(), "{}", rates);
+        }
+    }
+}
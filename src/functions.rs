@@ -0,0 +1,213 @@
+// Per-function e-table of the networked e-router.
+// Every function gets its own destination set and its own dispatcher (and so
+// its own weight table), made up of the e-computers that host the function.
+use crate::dispatch::{Destination, Dispatcher, DispatcherFactory};
+use crate::protocol::Feedback;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Which functions an e-computer hosts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Hosts {
+    All,
+    Only(HashSet<String>),
+}
+
+impl Hosts {
+    pub fn contains(&self, function: &str) -> bool {
+        match self {
+            Hosts::All => true,
+            Hosts::Only(functions) => functions.contains(function),
+        }
+    }
+}
+
+impl FromStr for Hosts {
+    type Err = String;
+
+    // `*` or a comma separated list of function names
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        if list == "*" {
+            return Ok(Hosts::All);
+        }
+        let functions: HashSet<String> = list
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect();
+        if functions.is_empty() {
+            return Err(format!("Empty function list {:?}", list));
+        }
+        Ok(Hosts::Only(functions))
+    }
+}
+
+impl fmt::Display for Hosts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hosts::All => write!(f, "*"),
+            Hosts::Only(functions) => {
+                let mut functions: Vec<&String> = functions.iter().collect();
+                functions.sort();
+                let names: Vec<&str> = functions.iter().map(|f| f.as_str()).collect();
+                write!(f, "{}", names.join(","))
+            }
+        }
+    }
+}
+
+pub struct FunctionTable {
+    factory: DispatcherFactory,
+    computers: Vec<(Destination, Hosts)>,
+    tables: HashMap<String, Box<dyn Dispatcher>>,
+}
+
+impl FunctionTable {
+    pub fn new(factory: DispatcherFactory) -> Self {
+        FunctionTable {
+            factory,
+            computers: Vec::new(),
+            tables: HashMap::new(),
+        }
+    }
+
+    pub fn add_computer(&mut self, destination: Destination, hosts: Hosts) {
+        for (function, dispatcher) in self.tables.iter_mut() {
+            if hosts.contains(function) {
+                dispatcher.add_destination(destination.clone());
+            }
+        }
+        self.computers.push((destination, hosts));
+    }
+
+    pub fn remove_computer(&mut self, port: u16) {
+        self.computers.retain(|(d, _)| d.port != port);
+        for dispatcher in self.tables.values_mut() {
+            dispatcher.remove_destination(port);
+        }
+    }
+
+    // The dispatcher for `function`, built on first use from the e-computers
+    // hosting it. None if none does, so clients can't make the e-router keep
+    // a table for every name they send.
+    fn dispatcher(&mut self, function: &str) -> Option<&mut Box<dyn Dispatcher>> {
+        if !self.tables.contains_key(function) {
+            let hosting: Vec<&Destination> = self
+                .computers
+                .iter()
+                .filter(|(_, hosts)| hosts.contains(function))
+                .map(|(destination, _)| destination)
+                .collect();
+            if hosting.is_empty() {
+                return None;
+            }
+            let mut dispatcher = (self.factory)(function);
+            for destination in hosting {
+                dispatcher.add_destination(destination.clone());
+            }
+            self.tables.insert(function.to_string(), dispatcher);
+        }
+        self.tables.get_mut(function)
+    }
+
+    pub fn select(&mut self, function: &str) -> Option<u16> {
+        self.dispatcher(function)?.select()
+    }
+
+    pub fn select_excluding(&mut self, function: &str, exclude: &[u16]) -> Option<u16> {
+        self.dispatcher(function)?.select_excluding(exclude)
+    }
+
+    pub fn on_completion(&mut self, function: &str, port: u16, latency: Duration) {
+        if let Some(dispatcher) = self.tables.get_mut(function) {
+            dispatcher.on_completion(port, latency);
+        }
+    }
+
+    pub fn on_failure(&mut self, function: &str, port: u16, penalty: Duration) {
+        if let Some(dispatcher) = self.tables.get_mut(function) {
+            dispatcher.on_failure(port, penalty);
+        }
+    }
+
+    pub fn on_rejected(&mut self, function: &str, port: u16) {
+        if let Some(dispatcher) = self.tables.get_mut(function) {
+            dispatcher.on_rejected(port);
+        }
+    }
+
+    pub fn on_feedback(&mut self, function: &str, port: u16, feedback: &Feedback) {
+        if let Some(dispatcher) = self.tables.get_mut(function) {
+            dispatcher.on_feedback(port, feedback);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::{Algorithm, Environment};
+
+    fn table(computers: &[&str]) -> FunctionTable {
+        let mut table = FunctionTable::new(Algorithm::RR.factory_in(Environment::default()));
+        for (i, hosts) in computers.iter().enumerate() {
+            table.add_computer(Destination::new(8080 + i as u16, 1), hosts.parse().unwrap());
+        }
+        table
+    }
+
+    // Ports chosen for `function` over `tasks` selections, sorted
+    fn chosen(table: &mut FunctionTable, function: &str, tasks: usize) -> Vec<u16> {
+        let mut ports: Vec<u16> = (0..tasks)
+            .map(|_| table.select(function).unwrap())
+            .collect();
+        ports.sort();
+        ports.dedup();
+        ports
+    }
+
+    #[test]
+    fn host_lists_parse() {
+        assert_eq!("*".parse(), Ok(Hosts::All));
+        let hosts: Hosts = "resize,thumbnail".parse().unwrap();
+        assert!(hosts.contains("resize") && hosts.contains("thumbnail"));
+        assert!(!hosts.contains("default"));
+        // Stray commas are ignored and the names print sorted
+        let hosts: Hosts = ",thumbnail,,resize,".parse().unwrap();
+        assert_eq!(hosts.to_string(), "resize,thumbnail");
+        assert_eq!(hosts.to_string().parse(), Ok(hosts));
+        assert!("".parse::<Hosts>().is_err());
+        assert!(",".parse::<Hosts>().is_err());
+    }
+
+    #[test]
+    fn tasks_go_to_computers_hosting_their_function() {
+        let mut table = table(&["resize", "resize,thumbnail", "*"]);
+        assert_eq!(chosen(&mut table, "resize", 6), [8080, 8081, 8082]);
+        assert_eq!(chosen(&mut table, "thumbnail", 6), [8081, 8082]);
+        assert_eq!(chosen(&mut table, "default", 6), [8082]);
+
+        // Tables already built take in e-computers that join or leave later
+        table.add_computer(Destination::new(8083, 1), "thumbnail".parse().unwrap());
+        assert_eq!(chosen(&mut table, "thumbnail", 6), [8081, 8082, 8083]);
+        table.remove_computer(8082);
+        assert_eq!(chosen(&mut table, "thumbnail", 6), [8081, 8083]);
+        assert_eq!(table.select_excluding("thumbnail", &[8081, 8083]), None);
+    }
+
+    #[test]
+    fn functions_no_computer_hosts_get_no_table() {
+        let mut table = table(&["resize"]);
+        assert_eq!(table.select("thumbnail"), None);
+        assert_eq!(table.select_excluding("thumbnail", &[]), None);
+        table.on_completion("thumbnail", 8080, Duration::from_millis(100));
+        assert!(!table.tables.contains_key("thumbnail"));
+
+        // Until one joins
+        table.add_computer(Destination::new(8081, 1), "thumbnail".parse().unwrap());
+        assert_eq!(table.select("thumbnail"), Some(8081));
+        assert!(table.tables.contains_key("thumbnail"));
+    }
+}
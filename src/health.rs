@@ -0,0 +1,128 @@
+// Active health checking of e-computers by the e-router.
+// Every interval each e-computer gets a TCP connect probe; after a number of
+// consecutive failures it is taken out of the e-tables, and the first
+// successful probe afterwards puts it back.
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+pub const DEFAULT_UNHEALTHY_AFTER: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+    // Time between probe rounds; zero turns probing off
+    pub interval: Duration,
+    pub timeout: Duration,
+    // Consecutive failures before an e-computer counts as down
+    pub unhealthy_after: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            interval: DEFAULT_PROBE_INTERVAL,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            unhealthy_after: DEFAULT_UNHEALTHY_AFTER,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Down,
+    Up,
+}
+
+// Health of one e-computer as seen from probes and forwarded tasks
+#[derive(Debug, Clone, Copy)]
+pub struct Health {
+    failures: u32,
+    healthy: bool,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            failures: 0,
+            healthy: true,
+        }
+    }
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    // Record the outcome of a probe or a forwarded task; returns the state
+    // change it caused, if any
+    pub fn record(&mut self, success: bool, unhealthy_after: u32) -> Option<Transition> {
+        if success {
+            self.failures = 0;
+            if !self.healthy {
+                self.healthy = true;
+                return Some(Transition::Up);
+            }
+        } else {
+            self.failures += 1;
+            if self.healthy && self.failures >= unhealthy_after.max(1) {
+                self.healthy = false;
+                return Some(Transition::Down);
+            }
+        }
+        None
+    }
+}
+
+// Whether the e-computer on `port` accepts a connection within `timeout`
+pub async fn probe(port: u16, timeout: Duration) -> bool {
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect(("127.0.0.1", port))).await,
+        Ok(Ok(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goes_down_after_consecutive_failures_only() {
+        let mut health = Health::default();
+        assert_eq!(health.record(false, 3), None);
+        assert_eq!(health.record(false, 3), None);
+        // A success in between starts the count again
+        assert_eq!(health.record(true, 3), None);
+        assert_eq!(health.record(false, 3), None);
+        assert_eq!(health.record(false, 3), None);
+        assert!(health.is_healthy());
+        assert_eq!(health.record(false, 3), Some(Transition::Down));
+        assert!(!health.is_healthy());
+        assert_eq!(health.failures(), 3);
+    }
+
+    #[test]
+    fn stays_down_until_the_first_success() {
+        let mut health = Health::default();
+        assert_eq!(health.record(false, 1), Some(Transition::Down));
+        for _ in 0..5 {
+            assert_eq!(health.record(false, 1), None);
+        }
+        assert_eq!(health.failures(), 6);
+        assert_eq!(health.record(true, 1), Some(Transition::Up));
+        assert!(health.is_healthy());
+        assert_eq!(health.failures(), 0);
+        assert_eq!(health.record(true, 1), None);
+    }
+
+    #[test]
+    fn zero_threshold_counts_as_one() {
+        let mut health = Health::default();
+        assert_eq!(health.record(false, 0), Some(Transition::Down));
+    }
+}
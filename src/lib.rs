@@ -0,0 +1,21 @@
+pub mod cli;
+pub mod clock;
+pub mod codec;
+pub mod coldstart;
+pub mod dispatch;
+pub mod etable;
+pub mod experiment;
+pub mod functions;
+pub mod health;
+pub mod loadgen;
+pub mod netem;
+pub mod pool;
+pub mod protocol;
+pub mod registration;
+pub mod router;
+pub mod service;
+pub mod sim;
+pub mod slo;
+pub mod stats;
+pub mod work;
+pub mod workload;
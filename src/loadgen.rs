@@ -0,0 +1,186 @@
+// Load generator driving an e-router, replacing `scripts/client.py` and
+// `scripts/manager-95.py`. Closed loop: N clients each send M tasks back to
+// back. Open loop: tasks arrive as a Poisson process regardless of how fast
+// earlier ones complete.
+use crate::codec;
+use crate::protocol::{self, Response, Status, Task};
+use crate::workload::SizeDistribution;
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use statrs::distribution::Exp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Arrivals {
+    Closed {
+        clients: usize,
+        tasks_per_client: usize,
+    },
+    Poisson {
+        rate: f64,
+        tasks: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub router_port: u16,
+    pub arrivals: Arrivals,
+    pub sizes: SizeDistribution,
+    pub function: String,
+    pub seed: Option<u64>,
+    // Deadline given to each task, counted from when it is sent
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+pub struct Outcome {
+    // End-to-end latency of each successful task in seconds, in completion order
+    pub latencies: Vec<f64>,
+    pub failures: usize,
+    // Failures by response status, `connection_error` when there was no response
+    pub failure_kinds: BTreeMap<String, usize>,
+    pub elapsed: Duration,
+}
+
+#[derive(Default)]
+struct Results {
+    latencies: Vec<f64>,
+    failures: usize,
+    failure_kinds: BTreeMap<String, usize>,
+}
+
+impl Results {
+    fn record(&mut self, result: io::Result<(Response, Duration)>) {
+        match result {
+            Ok((response, latency)) if response.status == Status::Ok => {
+                self.latencies.push(latency.as_secs_f64())
+            }
+            Ok((response, _)) => {
+                eprintln!(
+                    "Task {} failed: {} ({})",
+                    response.id,
+                    response.status,
+                    response.error.unwrap_or_default()
+                );
+                self.failure(response.status.to_string());
+            }
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                self.failure("connection_error".to_string());
+            }
+        }
+    }
+
+    fn failure(&mut self, kind: String) {
+        self.failures += 1;
+        *self.failure_kinds.entry(kind).or_default() += 1;
+    }
+}
+
+fn deadline(timeout: Option<Duration>) -> Option<u64> {
+    timeout.map(|timeout| protocol::unix_millis() + timeout.as_millis() as u64)
+}
+
+fn rng(seed: Option<u64>, stream: u64) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(stream)),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// Send one task on a fresh connection and time it, connect included
+pub async fn send_task(router_port: u16, task: &Task) -> io::Result<(Response, Duration)> {
+    let start_time = Instant::now();
+    let mut stream = TcpStream::connect(("127.0.0.1", router_port)).await?;
+    stream.set_nodelay(true)?;
+    codec::send_async(&mut stream, task).await?;
+    let response: Response = codec::recv_async(&mut stream).await?;
+    Ok((response, start_time.elapsed()))
+}
+
+pub async fn run(config: LoadConfig) -> Outcome {
+    let results = Arc::new(Mutex::new(Results::default()));
+    let start_time = Instant::now();
+    let mut handles = Vec::new();
+
+    match config.arrivals {
+        Arrivals::Closed {
+            clients,
+            tasks_per_client,
+        } => {
+            for client in 0..clients {
+                let config = config.clone();
+                let results = Arc::clone(&results);
+                handles.push(tokio::spawn(async move {
+                    let mut rng = rng(config.seed, client as u64 + 1);
+                    for n in 0..tasks_per_client {
+                        let task = Task {
+                            id: format!("task-{}-{}", client, n),
+                            size: config.sizes.sample(&mut rng),
+                            function: config.function.clone(),
+                            deadline: deadline(config.timeout),
+                        };
+                        let result = send_task(config.router_port, &task).await;
+                        results.lock().unwrap().record(result);
+                    }
+                }));
+            }
+        }
+        Arrivals::Poisson { rate, tasks } => {
+            let mut rng = rng(config.seed, 0);
+            let gaps = Exp::new(rate).unwrap();
+            let mut next_arrival = Instant::now();
+            for n in 0..tasks {
+                next_arrival += Duration::from_secs_f64(gaps.sample(&mut rng));
+                tokio::time::sleep_until(next_arrival).await;
+                let task = Task {
+                    id: format!("task-{}", n),
+                    size: config.sizes.sample(&mut rng),
+                    function: config.function.clone(),
+                    deadline: deadline(config.timeout),
+                };
+                let router_port = config.router_port;
+                let results = Arc::clone(&results);
+                handles.push(tokio::spawn(async move {
+                    let result = send_task(router_port, &task).await;
+                    results.lock().unwrap().record(result);
+                }));
+            }
+        }
+    }
+
+    for handle in handles {
+        let _ = handle.await;
+    }
+
+    let results = std::mem::take(&mut *results.lock().unwrap());
+    Outcome {
+        latencies: results.latencies,
+        failures: results.failures,
+        failure_kinds: results.failure_kinds,
+        elapsed: start_time.elapsed(),
+    }
+}
+
+// Write latencies one per line as `0.1234,`, the format of `scripts/exp-2-per-95/*.log`.
+// A path of `-` writes to stdout so the output can be piped into `e_report -`.
+pub fn write_log<P: AsRef<Path>>(path: P, latencies: &[f64]) -> io::Result<()> {
+    let mut log_file: BufWriter<Box<dyn Write>> = if path.as_ref() == Path::new("-") {
+        BufWriter::new(Box::new(io::stdout()))
+    } else {
+        BufWriter::new(Box::new(File::create(path)?))
+    };
+    for time in latencies {
+        writeln!(log_file, "{:.4},", time)?;
+    }
+    log_file.flush()
+}
@@ -0,0 +1,244 @@
+// Network emulation between the e-router and the e-computers.
+// Every hop adds its latency plus uniform jitter, and a packet lost on a hop
+// costs a retransmission timeout before it gets through. The e-router applies
+// it to the request and to the response leg of each task; the simulator uses
+// the same model, so distance trades off against load in both.
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Retransmission timeout when a link spec doesn't give one (Linux TCP minimum RTO)
+pub const DEFAULT_RETRANSMIT: Duration = Duration::from_millis(200);
+
+// Hops emulated at most, like an IP TTL; hop counts come from specs and
+// registrations and may be anything up to u32::MAX
+pub const MAX_HOPS: u32 = 255;
+
+// One hop, given as `LATENCY_MS[:JITTER_MS[:LOSS[:RTO_MS]]]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    pub latency: Duration,
+    // Each hop's latency varies uniformly by up to this much either way
+    pub jitter: Duration,
+    // Probability that a packet is lost on the hop and has to be resent
+    pub loss: f64,
+    pub retransmit: Duration,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            retransmit: DEFAULT_RETRANSMIT,
+        }
+    }
+}
+
+impl Link {
+    pub fn is_zero(&self) -> bool {
+        self.latency.is_zero() && self.jitter.is_zero() && self.loss == 0.0
+    }
+
+    // Time for one packet to cross this hop
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        let mut delay = self.latency.as_secs_f64();
+        if !self.jitter.is_zero() {
+            let jitter = self.jitter.as_secs_f64();
+            delay += rng.gen_range(-jitter..=jitter);
+        }
+        let mut delay = Duration::try_from_secs_f64(delay.max(0.0)).unwrap_or(Duration::MAX);
+        while self.loss > 0.0 && rng.gen::<f64>() < self.loss {
+            delay = delay.saturating_add(self.retransmit);
+        }
+        delay
+    }
+}
+
+impl FromStr for Link {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() > 4 {
+            return Err(format!("Too many fields in link {:?}", spec));
+        }
+        let field = |i: usize| -> Result<Option<f64>, String> {
+            parts
+                .get(i)
+                .map(|p| {
+                    p.parse::<f64>()
+                        .ok()
+                        .filter(|v| v.is_finite() && *v >= 0.0)
+                        .ok_or_else(|| format!("Invalid field {:?} in link {:?}", p, spec))
+                })
+                .transpose()
+        };
+        let millis = |i: usize, default: Duration| -> Result<Duration, String> {
+            match field(i)? {
+                Some(ms) => Duration::try_from_secs_f64(ms / 1000.0)
+                    .map_err(|_| format!("Time out of range in link {:?}", spec)),
+                None => Ok(default),
+            }
+        };
+        let loss = field(2)?.unwrap_or(0.0);
+        if loss >= 1.0 {
+            return Err(format!("Loss must be below 1 in link {:?}", spec));
+        }
+        Ok(Link {
+            latency: millis(0, Duration::ZERO)?,
+            jitter: millis(1, Duration::ZERO)?,
+            loss,
+            retransmit: millis(3, DEFAULT_RETRANSMIT)?,
+        })
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.latency.as_secs_f64() * 1000.0,
+            self.jitter.as_secs_f64() * 1000.0,
+            self.loss,
+            self.retransmit.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+// The links on the way to each e-computer: one default link, optionally
+// replaced for e-computers at a given number of hops. Given as
+// `LINK[,HOPS=LINK...]`, e.g. `2:0.5` or `2:0.5,3=10:2:0.01`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkModel {
+    pub default: Link,
+    pub by_hops: BTreeMap<u32, Link>,
+}
+
+impl NetworkModel {
+    pub fn link(&self, hops: u32) -> &Link {
+        self.by_hops.get(&hops).unwrap_or(&self.default)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.by_hops
+            .values()
+            .chain([&self.default])
+            .all(Link::is_zero)
+    }
+
+    // One-way delay to or from an e-computer `hops` away, counting at most
+    // `MAX_HOPS` of them
+    pub fn delay<R: Rng>(&self, hops: u32, rng: &mut R) -> Duration {
+        let link = self.link(hops);
+        if link.is_zero() {
+            return Duration::ZERO;
+        }
+        (0..hops.min(MAX_HOPS)).fold(Duration::ZERO, |delay, _| {
+            delay.saturating_add(link.sample(rng))
+        })
+    }
+}
+
+impl FromStr for NetworkModel {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut model = NetworkModel::default();
+        for part in spec.split(',') {
+            match part.split_once('=') {
+                Some((hops, link)) => {
+                    let hops = hops
+                        .parse()
+                        .map_err(|_| format!("Invalid hop count {:?} in {:?}", hops, spec))?;
+                    model.by_hops.insert(hops, link.parse()?);
+                }
+                None => model.default = part.parse()?,
+            }
+        }
+        Ok(model)
+    }
+}
+
+impl fmt::Display for NetworkModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default)?;
+        for (hops, link) in &self.by_hops {
+            write!(f, ",{}={}", hops, link)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn specs_round_trip_and_reject_impossible_fields() {
+        let model: NetworkModel = "2:0.5,3=10:2:0.01:100".parse().unwrap();
+        assert_eq!(model.to_string(), "2:0.5:0:200,3=10:2:0.01:100");
+        assert_eq!(model.link(3).retransmit, ms(100));
+        assert_eq!(model.link(1).latency, ms(2));
+        for spec in [
+            "inf",
+            "1e300",
+            "NaN",
+            "-1",
+            "1:inf",
+            "1:0:1",
+            "1:0:-0.1",
+            "1:0:0:1e300",
+            "1:2:0:3:4",
+            "x=1",
+        ] {
+            assert!(spec.parse::<NetworkModel>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn zero_links_cost_nothing_however_far() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let model = NetworkModel::default();
+        assert!(model.is_zero());
+        assert_eq!(model.delay(u32::MAX, &mut rng), Duration::ZERO);
+        // Hops past the cap aren't emulated
+        let model: NetworkModel = "1".parse().unwrap();
+        assert_eq!(model.delay(u32::MAX, &mut rng), ms(MAX_HOPS as u64));
+    }
+
+    #[test]
+    fn hop_counts_can_have_their_own_link() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let model: NetworkModel = "1,3=10".parse().unwrap();
+        assert_eq!(model.delay(2, &mut rng), ms(2));
+        assert_eq!(model.delay(3, &mut rng), ms(30));
+        assert_eq!(model.delay(0, &mut rng), Duration::ZERO);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds_and_loss_adds_timeouts() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let jittery: Link = "10:2".parse().unwrap();
+        for _ in 0..100 {
+            let delay = jittery.sample(&mut rng);
+            assert!(delay >= ms(8) && delay <= ms(12), "{:?}", delay);
+        }
+
+        let lossy: Link = "10:0:0.5:100".parse().unwrap();
+        let delays: Vec<Duration> = (0..100).map(|_| lossy.sample(&mut rng)).collect();
+        assert!(delays.iter().all(|d| (d.as_millis() - 10) % 100 == 0));
+        assert!(delays.contains(&ms(10)));
+        assert!(delays.iter().any(|d| *d >= ms(110)));
+    }
+}
@@ -0,0 +1,292 @@
+// Long-lived connections from the e-router to the e-computers.
+// Each destination gets a small pool of sockets; every socket multiplexes many
+// in-flight tasks by tagging frames with a request id, so forwarding a task no
+// longer pays for a TCP connect.
+use crate::codec;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+pub const DEFAULT_POOL_SIZE: usize = 2;
+
+type Reply = io::Result<Vec<u8>>;
+
+// Requests waiting for an answer, keyed by request id. `alive` is flipped under
+// the same lock so no request can be registered after the reader has given up.
+struct Pending {
+    alive: bool,
+    waiting: HashMap<u64, oneshot::Sender<Reply>>,
+}
+
+// Forgets a request that is no longer waited for, e.g. because the caller
+// timed out, so its entry doesn't linger until the connection dies
+struct Waiting<'a> {
+    pending: &'a Mutex<Pending>,
+    request_id: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.pending
+            .lock()
+            .unwrap()
+            .waiting
+            .remove(&self.request_id);
+    }
+}
+
+// Retires the connection if a frame was only partly written, because the
+// write failed or the caller gave up halfway; the socket is out of sync then
+struct Writing<'a> {
+    pending: &'a Mutex<Pending>,
+    finished: bool,
+}
+
+impl Drop for Writing<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.pending.lock().unwrap().alive = false;
+        }
+    }
+}
+
+pub struct MuxConnection {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Arc<Mutex<Pending>>,
+    next_id: AtomicU64,
+}
+
+impl MuxConnection {
+    // Connect to an e-computer and start the task that dispatches its responses
+    pub async fn connect(port: u16) -> io::Result<Self> {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream.set_nodelay(true)?;
+        let (mut reader, writer) = stream.into_split();
+        let pending = Arc::new(Mutex::new(Pending {
+            alive: true,
+            waiting: HashMap::new(),
+        }));
+
+        let pending_clone = Arc::clone(&pending);
+        tokio::spawn(async move {
+            let error = loop {
+                match codec::try_read_tagged_frame_async(&mut reader).await {
+                    Ok(Some((request_id, payload))) => {
+                        let waiter = pending_clone.lock().unwrap().waiting.remove(&request_id);
+                        match waiter {
+                            Some(tx) => {
+                                let _ = tx.send(Ok(payload));
+                            }
+                            None => eprintln!("Response for unknown request id {}", request_id),
+                        }
+                    }
+                    Ok(None) => break io::Error::from(io::ErrorKind::ConnectionAborted),
+                    Err(e) => break e,
+                }
+            };
+            // Fail everything still waiting on this socket
+            let mut pending = pending_clone.lock().unwrap();
+            pending.alive = false;
+            for (_, tx) in pending.waiting.drain() {
+                let _ = tx.send(Err(io::Error::new(error.kind(), error.to_string())));
+            }
+        });
+
+        Ok(MuxConnection {
+            writer: tokio::sync::Mutex::new(writer),
+            pending,
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.pending.lock().unwrap().alive
+    }
+
+    // Send one request and wait for its response. Safe to cancel: dropping
+    // the future forgets the request.
+    pub async fn call(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let request_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if !pending.alive {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            pending.waiting.insert(request_id, tx);
+        }
+        let _waiting = Waiting {
+            pending: &self.pending,
+            request_id,
+        };
+
+        {
+            let mut writer = self.writer.lock().await;
+            // The connection may have been retired while we waited, by a frame
+            // left half written; writing after it would garble this one too
+            if !self.is_alive() {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            let mut writing = Writing {
+                pending: &self.pending,
+                finished: false,
+            };
+            codec::write_tagged_frame_async(&mut *writer, request_id, payload).await?;
+            writing.finished = true;
+        }
+
+        rx.await
+            .unwrap_or_else(|_| Err(io::ErrorKind::ConnectionAborted.into()))
+    }
+}
+
+// A fixed number of connection slots to one e-computer, used round robin.
+// Slots are connected lazily and reconnected after their socket dies.
+pub struct ConnectionPool {
+    port: u16,
+    slots: Vec<tokio::sync::Mutex<Option<Arc<MuxConnection>>>>,
+    next: AtomicUsize,
+}
+
+impl ConnectionPool {
+    pub fn new(port: u16, size: usize) -> Self {
+        ConnectionPool {
+            port,
+            slots: (0..size.max(1))
+                .map(|_| tokio::sync::Mutex::new(None))
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+
+    async fn connection(&self) -> io::Result<Arc<MuxConnection>> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut slot = self.slots[index].lock().await;
+        match slot.as_ref() {
+            Some(connection) if connection.is_alive() => Ok(Arc::clone(connection)),
+            _ => {
+                let connection = Arc::new(MuxConnection::connect(self.port).await?);
+                *slot = Some(Arc::clone(&connection));
+                Ok(connection)
+            }
+        }
+    }
+
+    pub async fn call(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        self.connection().await?.call(payload).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // A listener standing in for an e-computer, and the port to reach it on
+    async fn listen() -> (TcpListener, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    // Read `count` requests off one connection, then hand it back
+    async fn requests(listener: &TcpListener, count: usize) -> (TcpStream, Vec<(u64, Vec<u8>)>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut requests = Vec::new();
+        while requests.len() < count {
+            let request = codec::try_read_tagged_frame_async(&mut stream).await;
+            requests.push(request.unwrap().unwrap());
+        }
+        (stream, requests)
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_get_their_own_responses() {
+        let (listener, port) = listen().await;
+        let connection = Arc::new(MuxConnection::connect(port).await.unwrap());
+        let calls: Vec<_> = (0..10)
+            .map(|i| {
+                let connection = Arc::clone(&connection);
+                tokio::spawn(async move { connection.call(format!("task-{}", i).as_bytes()).await })
+            })
+            .collect();
+
+        // Answer in the reverse order the requests came in
+        let (mut stream, requests) = requests(&listener, 10).await;
+        for (request_id, payload) in requests.into_iter().rev() {
+            let response = [b"done-".as_slice(), &payload].concat();
+            codec::write_tagged_frame_async(&mut stream, request_id, &response)
+                .await
+                .unwrap();
+        }
+        for (i, call) in calls.into_iter().enumerate() {
+            let response = call.await.unwrap().unwrap();
+            assert_eq!(response, format!("done-task-{}", i).as_bytes());
+        }
+        assert!(connection.is_alive());
+        assert!(connection.pending.lock().unwrap().waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_dropped_connection_fails_every_pending_call() {
+        let (listener, port) = listen().await;
+        let connection = Arc::new(MuxConnection::connect(port).await.unwrap());
+        let calls: Vec<_> = (0..5)
+            .map(|_| {
+                let connection = Arc::clone(&connection);
+                tokio::spawn(async move { connection.call(b"task").await })
+            })
+            .collect();
+
+        let (stream, _) = requests(&listener, 5).await;
+        drop(stream);
+        for call in calls {
+            assert!(call.await.unwrap().is_err());
+        }
+        assert!(!connection.is_alive());
+        let error = connection.call(b"task").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn calls_waiting_to_write_give_up_once_the_connection_is_retired() {
+        let (listener, port) = listen().await;
+        let connection = Arc::new(MuxConnection::connect(port).await.unwrap());
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        // Another caller is writing, then gives up halfway through its frame
+        let writer = connection.writer.lock().await;
+        let waiting = {
+            let connection = Arc::clone(&connection);
+            tokio::spawn(async move { connection.call(b"task").await })
+        };
+        while connection.pending.lock().unwrap().waiting.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        connection.pending.lock().unwrap().alive = false;
+        drop(writer);
+
+        // Had it written, it would be waiting for an answer that never comes
+        let waited = tokio::time::timeout(std::time::Duration::from_secs(1), waiting).await;
+        let error = waited
+            .expect("wrote after a partial frame")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+        assert!(connection.pending.lock().unwrap().waiting.is_empty());
+        // Nothing was written after the partial frame
+        drop(connection);
+        assert!(codec::try_read_tagged_frame_async(&mut stream)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
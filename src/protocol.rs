@@ -0,0 +1,255 @@
+// Messages exchanged between clients, the e-router and the e-computers.
+// On the wire each one is a JSON document inside a frame (see `codec`).
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Function invoked by tasks that don't name one, e.g. from older clients
+pub const DEFAULT_FUNCTION: &str = "default";
+
+fn default_function() -> String {
+    DEFAULT_FUNCTION.to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Task {
+    pub id: String,
+    pub size: u64,
+    #[serde(default = "default_function")]
+    pub function: String,
+    // Milliseconds since the Unix epoch after which the result is no use to
+    // the client. Everything runs on one host, so all processes share the clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl Task {
+    // Time left until the deadline, zero once it has passed; None without one
+    pub fn remaining(&self) -> Option<Duration> {
+        self.remaining_at(unix_millis())
+    }
+
+    // `remaining` at `now` milliseconds since the Unix epoch
+    pub fn remaining_at(&self, now: u64) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| Duration::from_millis(deadline.saturating_sub(now)))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+    }
+}
+
+// Outcome of a task, the same from the e-router and from the e-computers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    // No e-computer hosts the task's function
+    NoDestination,
+    // The e-computer's run queue is full
+    Overloaded,
+    // The deadline passed while the e-router waited for an e-computer
+    Timeout,
+    // The deadline passed before the task could start
+    Expired,
+    // Forwarding to the e-computers failed on every attempt
+    ComputerError,
+    // The request couldn't be parsed
+    BadRequest,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Status::Ok => "ok",
+            Status::NoDestination => "no_destination",
+            Status::Overloaded => "overloaded",
+            Status::Timeout => "timeout",
+            Status::Expired => "expired",
+            Status::ComputerError => "computer_error",
+            Status::BadRequest => "bad_request",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    pub id: String,
+    pub status: Status,
+    // What went wrong, for anything but `ok`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Whether the e-computer had to start a new instance of the function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cold_start: Option<bool>,
+    // The e-computer's load when it answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<Feedback>,
+}
+
+// Load an e-computer reports with each response, so the e-router doesn't have
+// to infer it from end-to-end latency alone
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Feedback {
+    // Tasks waiting in the run queue
+    pub queue_len: usize,
+    // Worker slots busy with other tasks
+    pub active_workers: usize,
+    pub workers: usize,
+    // How long this task ran, cold start included, and how long it waited in
+    // the run queue; absent if it never ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_ms: Option<f64>,
+}
+
+impl Feedback {
+    // Tasks the e-computer has on its hands, queued or running
+    pub fn backlog(&self) -> usize {
+        self.queue_len + self.active_workers
+    }
+
+    pub fn service_time(&self) -> Option<Duration> {
+        millis(self.service_ms)
+    }
+
+    pub fn queue_time(&self) -> Option<Duration> {
+        millis(self.queue_ms)
+    }
+
+    // The report without times that can't be, e.g. negative ones from a
+    // misbehaving e-computer
+    pub fn sanitized(self) -> Self {
+        Feedback {
+            service_ms: self.service_ms.filter(|_| self.service_time().is_some()),
+            queue_ms: self.queue_ms.filter(|_| self.queue_time().is_some()),
+            ..self
+        }
+    }
+}
+
+// A duration in milliseconds off the wire, if it is one
+fn millis(ms: Option<f64>) -> Option<Duration> {
+    Duration::try_from_secs_f64(ms? / 1000.0).ok()
+}
+
+impl Response {
+    pub fn ok(id: &str, cold_start: bool) -> Self {
+        Response {
+            id: id.to_string(),
+            status: Status::Ok,
+            error: None,
+            cold_start: Some(cold_start),
+            feedback: None,
+        }
+    }
+
+    pub fn error(id: &str, status: Status, error: impl Into<String>) -> Self {
+        Response {
+            id: id.to_string(),
+            status,
+            error: Some(error.into()),
+            cold_start: None,
+            feedback: None,
+        }
+    }
+
+    pub fn with_feedback(mut self, feedback: Feedback) -> Self {
+        self.feedback = Some(feedback);
+        self
+    }
+}
+
+fn all_functions() -> String {
+    "*".to_string()
+}
+
+// What an e-computer tells the e-router about itself when it joins
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Registration {
+    // Port the e-computer accepts tasks on, on the e-router's host
+    pub port: u16,
+    #[serde(default)]
+    pub hops: u32,
+    // Worker slots, i.e. tasks it runs at the same time. The e-router keeps
+    // one pooled connection per slot.
+    pub capacity: usize,
+    // `*` or a comma separated list, as after `@` on the e-router command line
+    #[serde(default = "all_functions")]
+    pub functions: String,
+}
+
+// Sent by e-computers to the e-router's control port
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    // Join (or rejoin) and start a lease
+    Register(Registration),
+    // Renew the lease
+    Heartbeat { port: u16 },
+    // Leave without waiting for the lease to run out
+    Deregister { port: u16 },
+    // Ask what the e-router has in flight, e.g. from `e_inspect`
+    Inspect,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlReply {
+    // The e-computer is registered until `lease_ms` from now
+    Ack { lease_ms: u64 },
+    // The e-router doesn't know the e-computer (e.g. its lease ran out) and it should register again
+    Unknown,
+    Inspection(Inspection),
+}
+
+// Tasks the e-router has forwarded and not heard back about, per e-computer
+// and one by one, oldest first, and how each function with a latency
+// objective is doing against it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub computers: Vec<ComputerLoad>,
+    pub requests: Vec<OutstandingRequest>,
+    #[serde(default)]
+    pub slos: Vec<SloReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComputerLoad {
+    pub port: u16,
+    pub in_flight: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutstandingRequest {
+    pub task: String,
+    pub function: String,
+    pub port: u16,
+    // Time since the e-router forwarded it
+    pub age_ms: u64,
+}
+
+// Tasks of a function answered since the e-router started and how many of
+// them missed its objective (`percentile`% within `target_ms`), including the
+// ones that failed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SloReport {
+    pub function: String,
+    pub percentile: f64,
+    pub target_ms: f64,
+    pub tasks: u64,
+    pub violations: u64,
+    // Whether few enough missed it
+    pub met: bool,
+}
@@ -0,0 +1,108 @@
+// E-computer side of the e-router's control protocol: register, then renew
+// the lease with heartbeats on the same connection for as long as the
+// process lives. If the e-router forgets us or goes away we register again.
+// On Ctrl-C or SIGTERM we deregister before exiting, so the e-router stops
+// routing to us at once instead of when the lease runs out.
+use crate::codec;
+use crate::protocol::{Control, ControlReply, Registration};
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+// Wait between attempts to reach the e-router
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Heartbeats per lease, so one lost heartbeat doesn't cost the lease
+const HEARTBEATS_PER_LEASE: u32 = 3;
+
+// Keep `registration` registered with the e-router's control port. Never returns.
+pub fn keep_registered(control_port: u16, registration: Registration) {
+    loop {
+        if let Err(error) = session(control_port, &registration) {
+            eprintln!("Registration with e-router failed: {}", error);
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// Deregister `port` and exit once the process is interrupted or terminated.
+// Exiting right after the e-router acknowledges leaves no time for the
+// heartbeats to register us again.
+pub fn deregister_on_shutdown(control_port: u16, port: u16) {
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                eprintln!("Can't watch for shutdown: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = runtime.block_on(shutdown_signal()) {
+            eprintln!("Can't watch for shutdown: {}", error);
+            return;
+        }
+        match deregister(control_port, port) {
+            Ok(()) => println!("Deregistered from e-router control port {}", control_port),
+            Err(error) => eprintln!("Deregistration from e-router failed: {}", error),
+        }
+        std::process::exit(0);
+    });
+}
+
+async fn shutdown_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+// Leave the e-router, waiting at most `RETRY_INTERVAL` for it to answer
+fn deregister(control_port: u16, port: u16) -> io::Result<()> {
+    let mut stream = TcpStream::connect(("127.0.0.1", control_port))?;
+    stream.set_read_timeout(Some(RETRY_INTERVAL))?;
+    codec::send(&mut stream, &Control::Deregister { port })?;
+    codec::recv::<_, ControlReply>(&mut stream)?;
+    Ok(())
+}
+
+// Register and send heartbeats until the e-router no longer knows us
+fn session(control_port: u16, registration: &Registration) -> io::Result<()> {
+    let mut stream = TcpStream::connect(("127.0.0.1", control_port))?;
+    stream.set_nodelay(true)?;
+
+    codec::send(&mut stream, &Control::Register(registration.clone()))?;
+    let lease = match codec::recv(&mut stream)? {
+        ControlReply::Ack { lease_ms } => Duration::from_millis(lease_ms),
+        ControlReply::Unknown => {
+            return Err(io::Error::other("E-router refused the registration"));
+        }
+        reply => {
+            return Err(io::Error::other(format!(
+                "Unexpected reply to registration: {:?}",
+                reply
+            )));
+        }
+    };
+    println!(
+        "Registered with e-router control port {} (lease {:?})",
+        control_port, lease
+    );
+
+    let heartbeat = Control::Heartbeat {
+        port: registration.port,
+    };
+    loop {
+        thread::sleep(lease / HEARTBEATS_PER_LEASE);
+        codec::send(&mut stream, &heartbeat)?;
+        if codec::recv::<_, ControlReply>(&mut stream)? == ControlReply::Unknown {
+            println!("E-router lost our registration, registering again");
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,1232 @@
+// The networked e-router shared by `e_router` and `e_router_v3`.
+// Tasks are routed per function (see `functions`). The e-table lock is only
+// held while choosing a destination and while feeding back a latency;
+// forwarding itself runs concurrently, bounded by a configurable number of
+// in-flight tasks. Both legs to an e-computer are delayed by the emulated
+// network (see `netem`) according to its hop count.
+// E-computers are either given on the command line or join at runtime through
+// the control port: they register, renew a lease with heartbeats and are
+// dropped from every e-table when the lease runs out. E-computers that stop
+// answering health probes are taken out of the e-tables until they recover.
+use crate::cli::Args;
+use crate::codec;
+use crate::dispatch::{
+    Algorithm, Bootstrap, Destination, DispatcherFactory, Environment, Estimator, Outstanding,
+};
+use crate::functions::{FunctionTable, Hosts};
+use crate::health::{
+    self, Health, HealthConfig, Transition, DEFAULT_PROBE_INTERVAL, DEFAULT_PROBE_TIMEOUT,
+    DEFAULT_UNHEALTHY_AFTER,
+};
+use crate::netem::NetworkModel;
+use crate::pool::{ConnectionPool, DEFAULT_POOL_SIZE};
+use crate::protocol::{Control, ControlReply, Feedback, Registration, Response, Status, Task};
+use crate::slo::{SloMetrics, Slos};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_MAX_INFLIGHT: usize = 64;
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(3);
+pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+pub const DEFAULT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(30);
+pub const DEFAULT_FAILURE_PENALTY: Duration = Duration::from_secs(5);
+
+// The options `RouterConfig::from_args` reads, for the usage messages of
+// `e_router` and `e_router_v3`, and what their values look like
+pub const USAGE_OPTIONS: &str = "[--max-inflight N] [--netem LINK[,HOPS=LINK...]] [--seed N] [--lease-ms N] [--probe-interval-ms N] [--probe-timeout-ms N] [--unhealthy-after N] [--max-attempts N] [--attempt-timeout-ms N] [--deadline-ms N] [--failure-penalty-ms N] [--estimator SPEC] [--bootstrap optimistic|average] [--slo SLO[,FUNCTION=SLO...]]";
+pub const USAGE_NOTES: &[&str] = &[
+    "LINK: LATENCY_MS[:JITTER_MS[:LOSS[:RTO_MS]]] per hop, HOPS=LINK for e-computers at HOPS hops",
+    "E-computers can also join at runtime with `e_computer --register <control-port>`",
+    "Health probes run every --probe-interval-ms (default 1000, 0 turns them off)",
+    "SLO: pP:TARGET_MS, e.g. p95:300; violations show in e_inspect",
+    "SPEC: ewma[:ALPHA], mean[:N], percentile[:P[:N]] or decay[:HALF_LIFE_MS] (default ewma:0.95)",
+];
+
+// An e-computer given on the command line as `port:hops[:pool][@function1,function2]`.
+// Without a function list it hosts every function.
+#[derive(Clone, Debug)]
+pub struct ComputerSpec {
+    pub destination: Destination,
+    pub pool_size: usize,
+    pub hosts: Hosts,
+}
+
+impl FromStr for ComputerSpec {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let (address, hosts) = match arg.split_once('@') {
+            Some((address, functions)) => (address, functions.parse()?),
+            None => (arg, Hosts::All),
+        };
+        let parts: Vec<&str> = address.split(':').collect();
+        let port = spec_field(&parts, 0, "port", arg)?
+            .ok_or_else(|| format!("Missing port in {:?}", arg))?;
+        let hops = spec_field(&parts, 1, "hops", arg)?.unwrap_or(0);
+        // Optional third field is the number of pooled connections to this e-computer
+        let pool_size = spec_field(&parts, 2, "pool size", arg)?.unwrap_or(DEFAULT_POOL_SIZE);
+        Ok(ComputerSpec {
+            destination: Destination::new(port, hops),
+            pool_size,
+            hosts,
+        })
+    }
+}
+
+// Field `i` of a colon-separated spec such as `port:hops:pool`, parsed as its
+// own type so an out of range value (say port 70000) is an error rather than
+// wrapped around
+pub(crate) fn spec_field<T: FromStr>(
+    parts: &[&str],
+    i: usize,
+    name: &str,
+    arg: &str,
+) -> Result<Option<T>, String> {
+    parts
+        .get(i)
+        .map(|p| {
+            p.parse()
+                .map_err(|_| format!("Invalid {} {:?} in {:?}", name, p, arg))
+        })
+        .transpose()
+}
+
+// How a task whose forwarding fails (refused, reset, timed out) is retried.
+// Every retry goes to an e-computer the task hasn't failed on yet, chosen by
+// the same algorithm, and every failure is fed back to it as a completion
+// taking `failure_penalty`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Attempts in total, the first one included
+    pub max_attempts: usize,
+    pub attempt_timeout: Duration,
+    // Time from receiving the task after which no new attempt starts
+    pub deadline: Duration,
+    pub failure_penalty: Duration,
+}
+
+impl RetryPolicy {
+    // When the last attempt may start for a task received at `received` with
+    // `remaining` until its own deadline, if it has one
+    fn deadline(&self, received: Instant, remaining: Option<Duration>) -> Instant {
+        let deadline = received + self.deadline;
+        remaining.map_or(deadline, |remaining| deadline.min(received + remaining))
+    }
+
+    // How long an attempt started at `now` may take: the attempt timeout, or
+    // less if `deadline` comes first, in which case it is cut short
+    fn attempt_limit(&self, now: Instant, deadline: Instant) -> (Duration, bool) {
+        let left = deadline.saturating_duration_since(now);
+        (self.attempt_timeout.min(left), left < self.attempt_timeout)
+    }
+
+    // Whether to stop after `attempts` failed by `now`, and with what status:
+    // `timeout` once the deadline passed, `computer_error` once the attempts
+    // are used up
+    fn give_up(&self, attempts: usize, now: Instant, deadline: Instant) -> Option<Status> {
+        if now >= deadline {
+            Some(Status::Timeout)
+        } else if attempts >= self.max_attempts {
+            Some(Status::ComputerError)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            attempt_timeout: DEFAULT_ATTEMPT_TIMEOUT,
+            deadline: DEFAULT_DEADLINE,
+            failure_penalty: DEFAULT_FAILURE_PENALTY,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RouterConfig {
+    // Upper bound on tasks forwarded to e-computers at the same time
+    pub max_inflight: usize,
+    // Delay added on the way to and from each e-computer
+    pub network: NetworkModel,
+    // Seed for the network emulation's jitter and loss
+    pub seed: Option<u64>,
+    // How long a registered e-computer stays without a heartbeat
+    pub lease: Duration,
+    pub health: HealthConfig,
+    pub retry: RetryPolicy,
+    // How latency samples become the weights policies route on
+    pub estimator: Estimator,
+    pub bootstrap: Bootstrap,
+    // Latency objective per function, for `SLO` routing and the counts in
+    // `Inspect` replies
+    pub slos: Slos,
+}
+
+impl RouterConfig {
+    // The configuration `USAGE_OPTIONS` describes, exiting with a message if
+    // an option is malformed
+    pub fn from_args(args: &Args) -> Self {
+        // The lease is checked a few times per lease, which a zero lease can't be
+        if args.get::<u64>("lease-ms") == Some(0) {
+            eprintln!("--lease-ms must be at least 1");
+            std::process::exit(1);
+        }
+        let millis =
+            |name: &str, default: Duration| args.get(name).map_or(default, Duration::from_millis);
+        RouterConfig {
+            max_inflight: args.get_or("max-inflight", DEFAULT_MAX_INFLIGHT),
+            network: args.get_or("netem", NetworkModel::default()),
+            seed: args.get("seed"),
+            lease: millis("lease-ms", DEFAULT_LEASE),
+            health: HealthConfig {
+                interval: millis("probe-interval-ms", DEFAULT_PROBE_INTERVAL),
+                timeout: millis("probe-timeout-ms", DEFAULT_PROBE_TIMEOUT),
+                unhealthy_after: args.get_or("unhealthy-after", DEFAULT_UNHEALTHY_AFTER),
+            },
+            retry: RetryPolicy {
+                max_attempts: args.get_or("max-attempts", DEFAULT_MAX_ATTEMPTS).max(1),
+                attempt_timeout: millis("attempt-timeout-ms", DEFAULT_ATTEMPT_TIMEOUT),
+                deadline: millis("deadline-ms", DEFAULT_DEADLINE),
+                failure_penalty: millis("failure-penalty-ms", DEFAULT_FAILURE_PENALTY),
+            },
+            estimator: args.get_or("estimator", Estimator::default()),
+            bootstrap: args.get_or("bootstrap", Bootstrap::default()),
+            slos: args.get_or("slo", Slos::default()),
+        }
+    }
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        RouterConfig {
+            max_inflight: DEFAULT_MAX_INFLIGHT,
+            network: NetworkModel::default(),
+            seed: None,
+            lease: DEFAULT_LEASE,
+            health: HealthConfig::default(),
+            retry: RetryPolicy::default(),
+            estimator: Estimator::default(),
+            bootstrap: Bootstrap::default(),
+            slos: Slos::default(),
+        }
+    }
+}
+
+// An e-computer the e-router forwards to
+struct Upstream {
+    hops: u32,
+    hosts: Hosts,
+    pool: ConnectionPool,
+}
+
+// An e-computer known to the e-router. Ones from the command line have no
+// lease and stay until the e-router stops. Only healthy ones are in the e-tables.
+struct Member {
+    upstream: Arc<Upstream>,
+    expires: Option<Instant>,
+    health: Health,
+}
+
+pub struct Router {
+    table: Mutex<FunctionTable>,
+    // Always locked after `table` when both are needed
+    members: Mutex<HashMap<u16, Member>>,
+    inflight: Semaphore,
+    network: NetworkModel,
+    rng: Mutex<StdRng>,
+    lease: Duration,
+    health: HealthConfig,
+    retry: RetryPolicy,
+    // Every forwarding attempt still waiting for its e-computer
+    outstanding: Outstanding,
+    // Tasks per function against its objective. Never held with another lock.
+    slo: Mutex<SloMetrics>,
+}
+
+impl Router {
+    pub fn new(algorithm: Algorithm, computers: Vec<ComputerSpec>, config: RouterConfig) -> Self {
+        Router::with_factory(
+            |environment| algorithm.factory_in(environment),
+            computers,
+            config,
+        )
+    }
+
+    // Route with dispatchers from the factory `factory` makes out of the
+    // router's environment, e.g. policies that aren't an `Algorithm`. The
+    // environment holds the router's outstanding table for load-aware ones.
+    pub fn with_factory(
+        factory: impl FnOnce(Environment) -> DispatcherFactory,
+        computers: Vec<ComputerSpec>,
+        config: RouterConfig,
+    ) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let environment = Environment {
+            estimator: config.estimator,
+            bootstrap: config.bootstrap,
+            slos: config.slos.clone(),
+            ..Environment::default()
+        };
+        let outstanding = environment.outstanding.clone();
+        let router = Router {
+            table: Mutex::new(FunctionTable::new(factory(environment))),
+            members: Mutex::new(HashMap::new()),
+            inflight: Semaphore::new(config.max_inflight.max(1)),
+            network: config.network,
+            rng: Mutex::new(rng),
+            lease: config.lease,
+            health: config.health,
+            retry: config.retry,
+            outstanding,
+            slo: Mutex::new(SloMetrics::new(config.slos)),
+        };
+        for computer in computers {
+            router.add_computer(computer, None);
+        }
+        router
+    }
+
+    // Put an e-computer in the e-tables, replacing any earlier entry for its port
+    fn add_computer(&self, computer: ComputerSpec, expires: Option<Instant>) {
+        let port = computer.destination.port;
+        let mut table = self.table.lock().unwrap();
+        let mut members = self.members.lock().unwrap();
+        if members.remove(&port).is_some() {
+            table.remove_computer(port);
+        }
+        let upstream = Upstream {
+            hops: computer.destination.hops,
+            hosts: computer.hosts.clone(),
+            pool: ConnectionPool::new(port, computer.pool_size),
+        };
+        table.add_computer(computer.destination, computer.hosts);
+        members.insert(
+            port,
+            Member {
+                upstream: Arc::new(upstream),
+                expires,
+                health: Health::default(),
+            },
+        );
+    }
+
+    fn remove_computer(&self, port: u16) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let removed = self.members.lock().unwrap().remove(&port).is_some();
+        if removed {
+            table.remove_computer(port);
+        }
+        removed
+    }
+
+    fn upstream(&self, port: u16) -> Option<Arc<Upstream>> {
+        let members = self.members.lock().unwrap();
+        members.get(&port).map(|m| Arc::clone(&m.upstream))
+    }
+
+    // Count a probe or forwarded task towards the e-computer's health, taking
+    // it out of or putting it back into the e-tables when its state flips
+    fn record_health(&self, port: u16, success: bool) {
+        let mut table = self.table.lock().unwrap();
+        let mut members = self.members.lock().unwrap();
+        let Some(member) = members.get_mut(&port) else {
+            return;
+        };
+        match member.health.record(success, self.health.unhealthy_after) {
+            Some(Transition::Down) => {
+                println!(
+                    "E-computer on port {} is unhealthy after {} failures",
+                    port,
+                    member.health.failures()
+                );
+                table.remove_computer(port);
+            }
+            Some(Transition::Up) => {
+                println!("E-computer on port {} recovered", port);
+                let upstream = &member.upstream;
+                table.add_computer(
+                    Destination::new(port, upstream.hops),
+                    upstream.hosts.clone(),
+                );
+            }
+            None => {}
+        }
+    }
+
+    // Probe every known e-computer each interval, all at once
+    pub async fn check_health(self: Arc<Self>) {
+        if self.health.interval.is_zero() {
+            return;
+        }
+        let mut interval = tokio::time::interval(self.health.interval);
+        loop {
+            interval.tick().await;
+            let ports: Vec<u16> = self.members.lock().unwrap().keys().copied().collect();
+            let probes: Vec<_> = ports
+                .into_iter()
+                .map(|port| {
+                    let timeout = self.health.timeout;
+                    (port, tokio::spawn(health::probe(port, timeout)))
+                })
+                .collect();
+            for (port, probe) in probes {
+                let success = probe.await.unwrap_or(false);
+                self.record_health(port, success);
+            }
+        }
+    }
+
+    // Accept clients forever; each connection may carry several tasks in sequence
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let router = Arc::clone(&self);
+            tokio::spawn(async move {
+                router
+                    .handle_client(stream)
+                    .await
+                    .unwrap_or_else(|error| eprintln!("Error: {}", error));
+            });
+        }
+    }
+
+    async fn handle_client(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        while let Some(request) = codec::try_read_frame_async(&mut stream).await? {
+            let response = self.handle_task(&request).await?;
+            codec::write_frame_async(&mut stream, &response).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_task(&self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let task: Task = match serde_json::from_slice(request) {
+            Ok(task) => task,
+            Err(error) => {
+                println!("Bad request: {}", error);
+                return respond(Response::error("", Status::BadRequest, error.to_string()));
+            }
+        };
+        println!("\n\nReceived task: {:?}", task);
+
+        let received = Instant::now();
+        let (status, response) = self.route(&task, request).await?;
+        self.slo
+            .lock()
+            .unwrap()
+            .record(&task.function, status, received.elapsed());
+        Ok(response)
+    }
+
+    // Forward a task, retrying on other e-computers as the policy allows, and
+    // return the response with its status
+    async fn route(&self, task: &Task, request: &[u8]) -> io::Result<(Status, Vec<u8>)> {
+        let _permit = self.inflight.acquire().await.unwrap();
+        if task.is_expired() {
+            println!("Task {} expired before it could be forwarded", task.id);
+            return reply(Response::error(
+                &task.id,
+                Status::Expired,
+                "Deadline passed before the task reached an e-computer",
+            ));
+        }
+        // The task's own deadline, if it has one, caps the retry deadline
+        let deadline = self.retry.deadline(Instant::now(), task.remaining());
+        // E-computers this task already failed on
+        let mut failed = Vec::new();
+        let mut attempts = 0;
+
+        loop {
+            // Only e-computers hosting the task's function are candidates. The
+            // attempt counts as outstanding from the moment it is chosen, so
+            // the next selection already sees it.
+            let (destination, attempt) = {
+                let mut table = self.table.lock().unwrap();
+                match table.select_excluding(&task.function, &failed) {
+                    Some(port) => (
+                        Some(port),
+                        Some(self.outstanding.start(&task.id, &task.function, port)),
+                    ),
+                    None => (None, None),
+                }
+            };
+            println!("Selected destination: {:?}", destination);
+            // The e-computer may have left between the two lookups
+            let Some((port, upstream)) =
+                destination.and_then(|port| Some((port, self.upstream(port)?)))
+            else {
+                if failed.is_empty() {
+                    println!("No destination available");
+                    return reply(Response::error(
+                        &task.id,
+                        Status::NoDestination,
+                        format!("No e-computer hosts function {:?}", task.function),
+                    ));
+                }
+                println!("No destination left to retry task {} on", task.id);
+                return reply(Response::error(
+                    &task.id,
+                    Status::ComputerError,
+                    format!("Failed on every e-computer tried: {:?}", failed),
+                ));
+            };
+
+            let (timeout, cut_short) = self.retry.attempt_limit(Instant::now(), deadline);
+            attempts += 1;
+            let start_time = Instant::now();
+            let response = tokio::time::timeout(timeout, self.forward(&upstream, request))
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+            let latency = start_time.elapsed();
+            // No longer outstanding before the dispatcher hears the outcome
+            drop(attempt);
+            // A timeout says the e-computer is busy, not that it is down
+            match &response {
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => {}
+                _ => self.record_health(port, response.is_ok()),
+            }
+
+            let response = match response {
+                Ok(response) => response,
+                Err(error) => {
+                    println!(
+                        "Forwarding task {} to port {} failed (attempt {} of {}): {}",
+                        task.id, port, attempts, self.retry.max_attempts, error
+                    );
+                    // Running out of the task's time before the attempt timeout
+                    // says nothing about the e-computer
+                    if cut_short && error.kind() == io::ErrorKind::TimedOut {
+                        self.table.lock().unwrap().on_rejected(&task.function, port);
+                    } else {
+                        failed.push(port);
+                        self.table.lock().unwrap().on_failure(
+                            &task.function,
+                            port,
+                            self.retry.failure_penalty,
+                        );
+                    }
+                    match self.retry.give_up(attempts, Instant::now(), deadline) {
+                        Some(Status::Timeout) => {
+                            println!("Task {} ran out of time", task.id);
+                            return reply(Response::error(
+                                &task.id,
+                                Status::Timeout,
+                                format!("Deadline passed waiting for port {}", port),
+                            ));
+                        }
+                        Some(status) => {
+                            return reply(Response::error(
+                                &task.id,
+                                status,
+                                format!(
+                                    "Failed after {} attempt(s), last on port {}: {}",
+                                    attempts, port, error
+                                ),
+                            ));
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            let (status, feedback) = match serde_json::from_slice::<Response>(&response) {
+                Ok(response) => (response.status, response.feedback.map(Feedback::sanitized)),
+                Err(error) => {
+                    self.table.lock().unwrap().on_rejected(&task.function, port);
+                    return reply(Response::error(
+                        &task.id,
+                        Status::ComputerError,
+                        format!("Invalid response from port {}: {}", port, error),
+                    ));
+                }
+            };
+            // Only completed tasks say something about service latency; a
+            // rejection from a full run queue doesn't
+            let mut table = self.table.lock().unwrap();
+            if let Some(feedback) = &feedback {
+                table.on_feedback(&task.function, port, feedback);
+            }
+            if status == Status::Ok {
+                table.on_completion(&task.function, port, latency);
+            } else {
+                table.on_rejected(&task.function, port);
+            }
+            drop(table);
+
+            println!("Task completed ({}). Latency: {:?}", status, latency);
+            return Ok((status, response));
+        }
+    }
+
+    // Send a task over the emulated network and bring its response back
+    async fn forward(&self, upstream: &Upstream, request: &[u8]) -> io::Result<Vec<u8>> {
+        self.network_delay(upstream.hops).await;
+        let response = upstream.pool.call(request).await?;
+        self.network_delay(upstream.hops).await;
+        Ok(response)
+    }
+
+    // One leg between the e-router and an e-computer `hops` away
+    async fn network_delay(&self, hops: u32) {
+        let delay = self.network.delay(hops, &mut *self.rng.lock().unwrap());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    // Accept e-computers on the control port; each keeps its connection open
+    // for heartbeats
+    pub async fn serve_control(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let router = Arc::clone(&self);
+            tokio::spawn(async move {
+                router
+                    .handle_control(stream)
+                    .await
+                    .unwrap_or_else(|error| eprintln!("Error: {}", error));
+            });
+        }
+    }
+
+    async fn handle_control(&self, mut stream: TcpStream) -> io::Result<()> {
+        while let Some(request) = codec::try_read_frame_async(&mut stream).await? {
+            let message: Control = serde_json::from_slice(&request)?;
+            let reply = self.handle_control_message(message)?;
+            codec::send_async(&mut stream, &reply).await?;
+        }
+        Ok(())
+    }
+
+    fn handle_control_message(&self, message: Control) -> io::Result<ControlReply> {
+        let ack = ControlReply::Ack {
+            lease_ms: self.lease.as_millis() as u64,
+        };
+        match message {
+            Control::Register(registration) => {
+                let computer = self.registered_computer(&registration)?;
+                let renewal = self.upstream(registration.port).is_some_and(|upstream| {
+                    upstream.hops == computer.destination.hops && upstream.hosts == computer.hosts
+                });
+                if renewal {
+                    self.renew(registration.port);
+                } else {
+                    println!(
+                        "Registered e-computer on port {} ({} hops, capacity {}, functions {})",
+                        registration.port, registration.hops, registration.capacity, computer.hosts
+                    );
+                    self.add_computer(computer, Some(Instant::now() + self.lease));
+                }
+                Ok(ack)
+            }
+            Control::Heartbeat { port } if self.renew(port) => Ok(ack),
+            Control::Heartbeat { .. } => Ok(ControlReply::Unknown),
+            Control::Deregister { port } => {
+                if self.remove_computer(port) {
+                    println!("Deregistered e-computer on port {}", port);
+                }
+                Ok(ack)
+            }
+            Control::Inspect => {
+                let ports: Vec<u16> = self.members.lock().unwrap().keys().copied().collect();
+                let mut inspection = self.outstanding.inspect(ports);
+                inspection.slos = self.slo.lock().unwrap().report();
+                Ok(ControlReply::Inspection(inspection))
+            }
+        }
+    }
+
+    fn registered_computer(&self, registration: &Registration) -> io::Result<ComputerSpec> {
+        let hosts = registration
+            .functions
+            .parse()
+            .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(ComputerSpec {
+            destination: Destination::new(registration.port, registration.hops),
+            pool_size: registration.capacity.max(1),
+            hosts,
+        })
+    }
+
+    // Extend the lease of a registered e-computer; false if it isn't registered
+    fn renew(&self, port: u16) -> bool {
+        let mut members = self.members.lock().unwrap();
+        match members.get_mut(&port) {
+            Some(member) => {
+                if member.expires.is_some() {
+                    member.expires = Some(Instant::now() + self.lease);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Drop e-computers whose lease ran out, checking a few times per lease
+    pub async fn expire_leases(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.lease / 4);
+        loop {
+            interval.tick().await;
+            self.expire(Instant::now());
+        }
+    }
+
+    // Drop the e-computers whose lease ran out by `now`
+    fn expire(&self, now: Instant) {
+        let expired: Vec<u16> = self
+            .members
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, m)| m.expires.is_some_and(|expires| expires <= now))
+            .map(|(port, _)| *port)
+            .collect();
+        for port in expired {
+            if self.remove_computer(port) {
+                println!("Lease of e-computer on port {} expired", port);
+            }
+        }
+    }
+}
+
+// Bind ephemeral ports for tasks and for registrations, announce them and
+// route tasks until the process is killed
+pub async fn run(
+    algorithm: Algorithm,
+    computers: Vec<ComputerSpec>,
+    config: RouterConfig,
+) -> io::Result<()> {
+    run_with_factory(
+        &algorithm.to_string(),
+        |environment| algorithm.factory_in(environment),
+        computers,
+        config,
+    )
+    .await
+}
+
+// Like `run`, with dispatchers as `Router::with_factory` makes them. `policy`
+// names them in the startup output.
+pub async fn run_with_factory(
+    policy: &str,
+    factory: impl FnOnce(Environment) -> DispatcherFactory,
+    computers: Vec<ComputerSpec>,
+    config: RouterConfig,
+) -> io::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    println!("E-router simulator listening on port {}", port);
+    let control = TcpListener::bind("127.0.0.1:0").await?;
+    println!(
+        "E-router control port {} (lease {:?})",
+        control.local_addr()?.port(),
+        config.lease
+    );
+    if !config.network.is_zero() {
+        println!("Emulating network links {}", config.network);
+    }
+    println!(
+        "Routing {} on {} latency estimates ({} bootstrap)",
+        policy, config.estimator, config.bootstrap
+    );
+    if !config.slos.is_empty() {
+        println!("Latency objectives {}", config.slos);
+    }
+
+    let router = Arc::new(Router::with_factory(factory, computers, config));
+    tokio::spawn(Arc::clone(&router).expire_leases());
+    tokio::spawn(Arc::clone(&router).check_health());
+    let control_router = Arc::clone(&router);
+    tokio::spawn(async move {
+        control_router
+            .serve_control(control)
+            .await
+            .unwrap_or_else(|error| eprintln!("Error: {}", error));
+    });
+    router.serve(listener).await
+}
+
+// An answer from the e-router itself rather than from an e-computer
+fn respond(response: Response) -> io::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&response)?)
+}
+
+// Like `respond`, keeping the status for the SLO counts
+fn reply(response: Response) -> io::Result<(Status, Vec<u8>)> {
+    Ok((response.status, respond(response)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::Dispatcher;
+
+    // A policy that isn't an `Algorithm`: the e-computer with the fewest
+    // tasks in flight, ties going to the highest port
+    struct FewestThenHighest {
+        ports: Vec<u16>,
+        outstanding: Outstanding,
+    }
+
+    impl Dispatcher for FewestThenHighest {
+        fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+            let table = self.outstanding.lock();
+            self.ports
+                .iter()
+                .copied()
+                .filter(|port| !exclude.contains(port))
+                .min_by_key(|port| (table.in_flight(*port), u16::MAX - port))
+        }
+
+        fn on_completion(&mut self, _: u16, _: Duration) {}
+
+        fn add_destination(&mut self, destination: Destination) {
+            self.ports.push(destination.port);
+        }
+
+        fn remove_destination(&mut self, port: u16) {
+            self.ports.retain(|p| *p != port);
+        }
+    }
+
+    #[test]
+    fn custom_policies_plug_in_with_a_factory() {
+        let computers = vec!["8080".parse().unwrap(), "8081".parse().unwrap()];
+        let router = Router::with_factory(
+            |environment| {
+                Box::new(move |_| {
+                    Box::new(FewestThenHighest {
+                        ports: Vec::new(),
+                        outstanding: environment.outstanding.clone(),
+                    })
+                })
+            },
+            computers,
+            RouterConfig::default(),
+        );
+        let select = || router.table.lock().unwrap().select("resize");
+        assert_eq!(select(), Some(8081));
+        // The policy sees the router's own tasks in flight
+        let attempt = router.outstanding.start("task-1", "resize", 8081);
+        assert_eq!(select(), Some(8080));
+        drop(attempt);
+        assert_eq!(select(), Some(8081));
+    }
+
+    #[test]
+    fn computer_specs_reject_out_of_range_fields() {
+        let spec: ComputerSpec = "8080:2:4@resize".parse().unwrap();
+        assert_eq!(spec.destination, Destination::new(8080, 2));
+        assert_eq!(spec.pool_size, 4);
+        assert!(spec.hosts.contains("resize"));
+        let spec: ComputerSpec = "8080".parse().unwrap();
+        assert_eq!(spec.destination.hops, 0);
+        assert_eq!(spec.pool_size, DEFAULT_POOL_SIZE);
+
+        assert!("70000:1".parse::<ComputerSpec>().is_err());
+        assert!("8080:-1".parse::<ComputerSpec>().is_err());
+        assert!("8080:4294967296".parse::<ComputerSpec>().is_err());
+        assert!("8080:1:x".parse::<ComputerSpec>().is_err());
+        assert!(":1".parse::<ComputerSpec>().is_err());
+    }
+
+    // What the router told a `Recording` dispatcher, in order
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Selected { excluding: Vec<u16> },
+        Failed { port: u16, penalty: Duration },
+        Completed { port: u16 },
+        Rejected { port: u16 },
+    }
+
+    // Picks the first e-computer added that isn't excluded and records what
+    // it is asked and told
+    struct Recording {
+        ports: Vec<u16>,
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl Dispatcher for Recording {
+        fn select_excluding(&mut self, exclude: &[u16]) -> Option<u16> {
+            let excluding = exclude.to_vec();
+            self.events
+                .lock()
+                .unwrap()
+                .push(Event::Selected { excluding });
+            self.ports
+                .iter()
+                .copied()
+                .find(|port| !exclude.contains(port))
+        }
+
+        fn on_completion(&mut self, port: u16, _: Duration) {
+            self.events.lock().unwrap().push(Event::Completed { port });
+        }
+
+        fn on_failure(&mut self, port: u16, penalty: Duration) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(Event::Failed { port, penalty });
+        }
+
+        fn on_rejected(&mut self, port: u16) {
+            self.events.lock().unwrap().push(Event::Rejected { port });
+        }
+
+        fn add_destination(&mut self, destination: Destination) {
+            self.ports.push(destination.port);
+        }
+
+        fn remove_destination(&mut self, port: u16) {
+            self.ports.retain(|p| *p != port);
+        }
+    }
+
+    fn recording_router(ports: &[u16], retry: RetryPolicy) -> (Router, Arc<Mutex<Vec<Event>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let router = Router::with_factory(
+            move |_| {
+                Box::new(move |_| {
+                    Box::new(Recording {
+                        ports: Vec::new(),
+                        events: Arc::clone(&recorded),
+                    })
+                })
+            },
+            ports
+                .iter()
+                .map(|port| port.to_string().parse().unwrap())
+                .collect(),
+            RouterConfig {
+                retry,
+                ..RouterConfig::default()
+            },
+        );
+        (router, events)
+    }
+
+    // Distinct ports nothing listens on, so connecting is refused. Bound
+    // together so they can't be handed out twice.
+    async fn refusing<const N: usize>() -> [u16; N] {
+        let mut listeners = Vec::new();
+        for _ in 0..N {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        std::array::from_fn(|i| listeners[i].local_addr().unwrap().port())
+    }
+
+    // An e-computer that completes every task at once
+    async fn answering() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(Some((request_id, request))) =
+                codec::try_read_tagged_frame_async(&mut stream).await
+            {
+                let task: Task = serde_json::from_slice(&request).unwrap();
+                let response = serde_json::to_vec(&Response::ok(&task.id, false)).unwrap();
+                codec::write_tagged_frame_async(&mut stream, request_id, &response)
+                    .await
+                    .unwrap();
+            }
+        });
+        port
+    }
+
+    // An e-computer that takes tasks and never answers
+    async fn silent() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(Some(_)) = codec::try_read_tagged_frame_async(&mut stream).await {}
+        });
+        port
+    }
+
+    async fn route(router: &Router) -> Status {
+        let task = Task {
+            id: "task-1".to_string(),
+            size: 1,
+            function: "resize".to_string(),
+            deadline: None,
+        };
+        let request = serde_json::to_vec(&task).unwrap();
+        router.route(&task, &request).await.unwrap().0
+    }
+
+    fn retry(max_attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            failure_penalty: Duration::from_secs(7),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_go_to_computers_not_tried_yet() {
+        let third = answering().await;
+        let [first, second] = refusing().await;
+        let (router, events) = recording_router(&[first, second, third], retry(3));
+        assert_eq!(route(&router).await, Status::Ok);
+
+        let penalty = Duration::from_secs(7);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::Selected { excluding: vec![] },
+                Event::Failed {
+                    port: first,
+                    penalty
+                },
+                Event::Selected {
+                    excluding: vec![first]
+                },
+                Event::Failed {
+                    port: second,
+                    penalty
+                },
+                Event::Selected {
+                    excluding: vec![first, second]
+                },
+                Event::Completed { port: third },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_attempts() {
+        let third = answering().await;
+        let [first, second] = refusing().await;
+        let (router, events) = recording_router(&[first, second, third], retry(2));
+        assert_eq!(route(&router).await, Status::ComputerError);
+
+        let penalty = Duration::from_secs(7);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::Selected { excluding: vec![] },
+                Event::Failed {
+                    port: first,
+                    penalty
+                },
+                Event::Selected {
+                    excluding: vec![first]
+                },
+                Event::Failed {
+                    port: second,
+                    penalty
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_stop_once_every_computer_failed() {
+        let [first, second] = refusing().await;
+        let (router, events) = recording_router(&[first, second], retry(5));
+        assert_eq!(route(&router).await, Status::ComputerError);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[4],
+            Event::Selected {
+                excluding: vec![first, second]
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn attempts_cut_short_by_the_deadline_are_not_failures() {
+        let port = silent().await;
+        let retry = RetryPolicy {
+            deadline: Duration::from_millis(50),
+            ..retry(3)
+        };
+        let (router, events) = recording_router(&[port], retry);
+        // The router's deadline ends the retries, not the e-computer
+        assert_eq!(route(&router).await, Status::Timeout);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::Selected { excluding: vec![] },
+                Event::Rejected { port },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn attempts_given_the_full_timeout_are_failures() {
+        let port = silent().await;
+        let retry = RetryPolicy {
+            attempt_timeout: Duration::from_millis(50),
+            ..retry(1)
+        };
+        let (router, events) = recording_router(&[port], retry);
+        assert_eq!(route(&router).await, Status::ComputerError);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::Selected { excluding: vec![] },
+                Event::Failed {
+                    port,
+                    penalty: Duration::from_secs(7)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deadlines_limit_the_attempts() {
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            attempt_timeout: Duration::from_secs(10),
+            deadline: Duration::from_secs(30),
+            ..RetryPolicy::default()
+        };
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        // The task's own deadline only ever brings the router's forward
+        assert_eq!(retry.deadline(now, None), now + secs(30));
+        assert_eq!(retry.deadline(now, Some(secs(5))), now + secs(5));
+        assert_eq!(retry.deadline(now, Some(secs(60))), now + secs(30));
+
+        assert_eq!(retry.attempt_limit(now, now + secs(30)), (secs(10), false));
+        assert_eq!(retry.attempt_limit(now, now + secs(10)), (secs(10), false));
+        assert_eq!(retry.attempt_limit(now, now + secs(4)), (secs(4), true));
+        assert_eq!(
+            retry.attempt_limit(now + secs(5), now + secs(4)),
+            (secs(0), true)
+        );
+
+        let deadline = now + secs(1);
+        assert_eq!(retry.give_up(1, now, deadline), None);
+        assert_eq!(retry.give_up(3, now, deadline), Some(Status::ComputerError));
+        assert_eq!(retry.give_up(1, deadline, deadline), Some(Status::Timeout));
+        assert_eq!(
+            retry.give_up(3, now + secs(2), deadline),
+            Some(Status::Timeout)
+        );
+    }
+
+    #[test]
+    fn tasks_have_no_time_left_past_their_deadline() {
+        let mut task = Task {
+            id: "task-1".to_string(),
+            size: 1,
+            function: "resize".to_string(),
+            deadline: None,
+        };
+        assert_eq!(task.remaining_at(1000), None);
+        task.deadline = Some(1000);
+        assert_eq!(task.remaining_at(400), Some(Duration::from_millis(600)));
+        assert_eq!(task.remaining_at(1000), Some(Duration::ZERO));
+        assert_eq!(task.remaining_at(2000), Some(Duration::ZERO));
+        assert!(task.is_expired());
+    }
+
+    #[test]
+    fn registered_computers_hold_a_lease() {
+        let lease = Duration::from_millis(50);
+        let config = RouterConfig {
+            lease,
+            ..RouterConfig::default()
+        };
+        let router = Router::new(
+            Algorithm::RR,
+            vec!["8080@thumbnail".parse().unwrap()],
+            config,
+        );
+        let control = |message| router.handle_control_message(message).unwrap();
+        let select = |function| router.table.lock().unwrap().select(function);
+        let ack = ControlReply::Ack { lease_ms: 50 };
+        let registration = Registration {
+            port: 8081,
+            hops: 1,
+            capacity: 2,
+            functions: "resize".to_string(),
+        };
+
+        assert_eq!(control(Control::Register(registration.clone())), ack);
+        assert_eq!(select("thumbnail"), Some(8080));
+        assert_eq!(select("resize"), Some(8081));
+        let expires = |port| router.members.lock().unwrap()[&port].expires;
+        let registered = expires(8081).unwrap();
+
+        // A heartbeat, or the same registration again, renews the lease
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(control(Control::Heartbeat { port: 8081 }), ack);
+        let renewed = expires(8081).unwrap();
+        assert!(renewed > registered);
+        assert_eq!(control(Control::Register(registration.clone())), ack);
+        router.expire(renewed - Duration::from_millis(1));
+        assert!(expires(8081).is_some());
+
+        // Once it runs out the e-computer is gone from every e-table, while
+        // the ones from the command line stay
+        router.expire(expires(8081).unwrap());
+        assert!(!router.members.lock().unwrap().contains_key(&8081));
+        assert_eq!(select("resize"), None);
+        assert_eq!(select("thumbnail"), Some(8080));
+        assert_eq!(expires(8080), None);
+        assert_eq!(
+            control(Control::Heartbeat { port: 8081 }),
+            ControlReply::Unknown
+        );
+
+        // Until it registers again
+        assert_eq!(control(Control::Register(registration)), ack);
+        assert_eq!(select("resize"), Some(8081));
+    }
+
+    #[test]
+    fn deregistered_computers_leave_at_once() {
+        let router = Router::new(Algorithm::RR, Vec::new(), RouterConfig::default());
+        let control = |message| router.handle_control_message(message).unwrap();
+        let registration = Registration {
+            port: 8081,
+            hops: 0,
+            capacity: 1,
+            functions: "*".to_string(),
+        };
+        control(Control::Register(registration));
+        assert_eq!(router.table.lock().unwrap().select("resize"), Some(8081));
+
+        control(Control::Deregister { port: 8081 });
+        assert_eq!(router.table.lock().unwrap().select("resize"), None);
+        assert_eq!(
+            control(Control::Heartbeat { port: 8081 }),
+            ControlReply::Unknown
+        );
+        // Leaving twice is harmless
+        control(Control::Deregister { port: 8081 });
+    }
+
+    #[tokio::test]
+    async fn tasks_for_functions_nobody_hosts_have_no_destination() {
+        let router = Router::new(
+            Algorithm::RR,
+            vec!["8080@thumbnail".parse().unwrap()],
+            RouterConfig::default(),
+        );
+        assert_eq!(route(&router).await, Status::NoDestination);
+    }
+}
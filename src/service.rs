@@ -0,0 +1,209 @@
+// Service time models for the e-computers.
+// Every model but bimodal keeps the mean at `size / speed` seconds and only
+// changes how individual tasks spread around it. Bimodal keeps its fast mode
+// there, so its mean is `1 - P + P * FACTOR` times that.
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use statrs::distribution::{Exp, LogNormal};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Longest service time drawn, so a heavy tail can't produce a sample that
+// overflows a `Duration` or the simulator's clock
+pub const MAX_SERVICE_TIME: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceTime {
+    // Exactly size / speed
+    Deterministic,
+    // Exponentially distributed around size / speed
+    Exponential,
+    // Log-normal with the given shape (sigma of the underlying normal)
+    LogNormal {
+        sigma: f64,
+    },
+    // size / speed most of the time, `slow_factor` times longer with probability `slow_probability`
+    Bimodal {
+        slow_probability: f64,
+        slow_factor: f64,
+    },
+}
+
+impl FromStr for ServiceTime {
+    type Err = String;
+
+    // det | exp | lognormal:SIGMA | bimodal:P:FACTOR
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let param = |i: usize| -> Result<f64, String> {
+            parts
+                .get(i)
+                .ok_or_else(|| format!("Missing parameter in service time {:?}", spec))?
+                .parse()
+                .ok()
+                .filter(|p: &f64| p.is_finite())
+                .ok_or_else(|| format!("Invalid parameter in service time {:?}", spec))
+        };
+        match parts[0] {
+            "det" => Ok(ServiceTime::Deterministic),
+            "exp" => Ok(ServiceTime::Exponential),
+            "lognormal" => {
+                let sigma = param(1)?;
+                if sigma <= 0.0 {
+                    return Err(format!("Log-normal sigma must be positive in {:?}", spec));
+                }
+                Ok(ServiceTime::LogNormal { sigma })
+            }
+            "bimodal" => {
+                let slow_probability = param(1)?;
+                let slow_factor = param(2)?;
+                if !(0.0..=1.0).contains(&slow_probability) || slow_factor < 0.0 {
+                    return Err(format!("Invalid bimodal parameters in {:?}", spec));
+                }
+                Ok(ServiceTime::Bimodal {
+                    slow_probability,
+                    slow_factor,
+                })
+            }
+            _ => Err(format!(
+                "Invalid service time {:?}. Choose det, exp, lognormal:SIGMA or bimodal:P:FACTOR.",
+                spec
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ServiceTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceTime::Deterministic => write!(f, "det"),
+            ServiceTime::Exponential => write!(f, "exp"),
+            ServiceTime::LogNormal { sigma } => write!(f, "lognormal:{}", sigma),
+            ServiceTime::Bimodal {
+                slow_probability,
+                slow_factor,
+            } => write!(f, "bimodal:{}:{}", slow_probability, slow_factor),
+        }
+    }
+}
+
+// A service time model with its own random number generator, so runs with
+// the same seed draw the same sequence of service times
+pub struct ServiceModel {
+    kind: ServiceTime,
+    rng: StdRng,
+}
+
+impl ServiceModel {
+    pub fn new(kind: ServiceTime, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        ServiceModel { kind, rng }
+    }
+
+    pub fn kind(&self) -> ServiceTime {
+        self.kind
+    }
+
+    // Draw the processing time of a task of `size` on a computer of `speed`
+    pub fn sample(&mut self, size: u64, speed: u64) -> Duration {
+        let mean = size as f64 / speed as f64;
+        if !mean.is_finite() || mean <= 0.0 {
+            return Duration::ZERO;
+        }
+        let seconds = match self.kind {
+            ServiceTime::Deterministic => mean,
+            ServiceTime::Exponential => Exp::new(1.0 / mean).unwrap().sample(&mut self.rng),
+            ServiceTime::LogNormal { sigma } => {
+                // Pick the location so the mean (not the median) is size / speed
+                let location = mean.ln() - sigma * sigma / 2.0;
+                LogNormal::new(location, sigma)
+                    .unwrap()
+                    .sample(&mut self.rng)
+            }
+            ServiceTime::Bimodal {
+                slow_probability,
+                slow_factor,
+            } => {
+                if self.rng.gen::<f64>() < slow_probability {
+                    mean * slow_factor
+                } else {
+                    mean
+                }
+            }
+        };
+        Duration::from_secs_f64(seconds.max(0.0).min(MAX_SERVICE_TIME.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mean in seconds of many draws for a task whose mean is 0.05 s
+    fn mean_of(kind: ServiceTime, draws: usize) -> f64 {
+        let mut model = ServiceModel::new(kind, Some(1));
+        let total: f64 = (0..draws)
+            .map(|_| model.sample(5000, 100000).as_secs_f64())
+            .sum();
+        total / draws as f64
+    }
+
+    #[test]
+    fn models_keep_their_means() {
+        assert!((mean_of(ServiceTime::Deterministic, 10) - 0.05).abs() < 1e-12);
+        for kind in ["exp", "lognormal:0.5"] {
+            let mean = mean_of(kind.parse().unwrap(), 100_000);
+            assert!((mean - 0.05).abs() < 0.001, "{} mean {}", kind, mean);
+        }
+        // The fast mode stays at size / speed, the mean doesn't
+        let mean = mean_of("bimodal:0.1:10".parse().unwrap(), 100_000);
+        let expected = 0.05 * (1.0 - 0.1 + 0.1 * 10.0);
+        assert!((mean - expected).abs() < 0.002, "bimodal mean {}", mean);
+    }
+
+    #[test]
+    fn the_same_seed_draws_the_same_times() {
+        let draws = |seed| {
+            let mut model = ServiceModel::new(ServiceTime::Exponential, Some(seed));
+            (0..10)
+                .map(|_| model.sample(5000, 100000))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+    }
+
+    #[test]
+    fn parameters_must_be_finite_and_samples_bounded() {
+        for spec in [
+            "bimodal:0.5:inf",
+            "bimodal:NaN:2",
+            "lognormal:inf",
+            "lognormal:0",
+        ] {
+            assert!(spec.parse::<ServiceTime>().is_err(), "{}", spec);
+        }
+        assert_eq!(
+            "bimodal:0.5:2".parse::<ServiceTime>().unwrap().to_string(),
+            "bimodal:0.5:2"
+        );
+        // A finite but huge tail is cut off rather than panicking
+        let mut model = ServiceModel::new(ServiceTime::LogNormal { sigma: 40.0 }, Some(3));
+        for _ in 0..1000 {
+            assert!(model.sample(5000, 100000) <= MAX_SERVICE_TIME);
+        }
+        let mut model = ServiceModel::new(
+            ServiceTime::Bimodal {
+                slow_probability: 1.0,
+                slow_factor: f64::MAX,
+            },
+            Some(3),
+        );
+        assert_eq!(model.sample(5000, 100000), MAX_SERVICE_TIME);
+    }
+}
@@ -0,0 +1,440 @@
+// Discrete-event simulation of an e-router in front of modeled e-computers.
+// The routing algorithms are the real dispatchers from `dispatch`, running on
+// a virtual clock; e-computers, network hops and arrivals are modeled, so a
+// run takes as long as its events take to process rather than as long as the
+// tasks would take, and the same seed always gives the same run.
+use crate::clock::Clock;
+use crate::coldstart::{ColdStartConfig, InstancePool};
+use crate::dispatch::{
+    Algorithm, Bootstrap, Destination, Environment, Estimator, Outstanding, Ticket,
+};
+use crate::functions::{FunctionTable, Hosts};
+use crate::loadgen::Arrivals;
+use crate::netem::NetworkModel;
+use crate::protocol::Feedback;
+use crate::router::spec_field;
+use crate::service::{ServiceModel, ServiceTime};
+use crate::slo::Slos;
+use crate::workload::SizeDistribution;
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use statrs::distribution::Exp;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+
+// A modeled e-computer given as `speed:hops[:workers][@function1,function2]`
+#[derive(Clone, Debug)]
+pub struct SimComputer {
+    pub speed: u64,
+    pub hops: u32,
+    pub workers: usize,
+    pub hosts: Hosts,
+}
+
+impl FromStr for SimComputer {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let (model, hosts) = match arg.split_once('@') {
+            Some((model, functions)) => (model, functions.parse()?),
+            None => (arg, Hosts::All),
+        };
+        let parts: Vec<&str> = model.split(':').collect();
+        let speed = spec_field(&parts, 0, "speed", arg)?
+            .ok_or_else(|| format!("Missing speed in {:?}", arg))?;
+        Ok(SimComputer {
+            speed,
+            hops: spec_field(&parts, 1, "hops", arg)?.unwrap_or(0),
+            workers: spec_field::<usize>(&parts, 2, "workers", arg)?
+                .unwrap_or(1)
+                .max(1),
+            hosts,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub algorithm: Algorithm,
+    pub computers: Vec<SimComputer>,
+    pub arrivals: Arrivals,
+    pub sizes: SizeDistribution,
+    pub function: String,
+    pub service: ServiceTime,
+    // Links between the e-router and the e-computers, crossed both ways
+    pub network: NetworkModel,
+    pub cold_start: ColdStartConfig,
+    // How latency samples become the weights policies route on
+    pub estimator: Estimator,
+    pub bootstrap: Bootstrap,
+    // Latency objective per function, for `SLO` routing
+    pub slos: Slos,
+    pub seed: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SimOutcome {
+    // End-to-end latency of each task in seconds, in completion order
+    pub latencies: Vec<f64>,
+    // Tasks the e-router found no destination for
+    pub failures: usize,
+    // Virtual time from the first arrival to the last response
+    pub elapsed: Duration,
+    // Tasks sent to each e-computer, in the order of `SimConfig::computers`
+    pub assigned: Vec<usize>,
+    pub cold_starts: usize,
+    pub events: u64,
+}
+
+#[derive(Debug)]
+struct SimTask {
+    size: u64,
+    // When the task reached the e-router
+    sent: Duration,
+    computer: usize,
+    // The closed-loop client waiting for it
+    client: Option<usize>,
+    // Keeps the task in the e-router's outstanding table until it returns
+    attempt: Ticket,
+    // When it reached its e-computer and when a worker took it
+    delivered: Duration,
+    started: Duration,
+    // What the e-computer reports with the response
+    feedback: Option<Feedback>,
+}
+
+#[derive(Debug)]
+enum Event {
+    // A new task reaches the e-router
+    Arrival { client: Option<usize> },
+    // A task reaches its e-computer
+    Delivered(SimTask),
+    // An e-computer finishes a task
+    Finished(SimTask),
+    // A response reaches the e-router and is passed on to the client
+    Returned(SimTask),
+}
+
+// Events are ordered by time and then by when they were scheduled, so ties
+// always resolve the same way
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    event: Event,
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap; the earliest event must come out first
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+// A modeled e-computer: `workers` slots in front of an unbounded FIFO queue
+struct Computer {
+    speed: u64,
+    hops: u32,
+    workers: usize,
+    busy: usize,
+    queue: VecDeque<SimTask>,
+    service: ServiceModel,
+    instances: InstancePool,
+}
+
+struct Simulation<'a> {
+    config: &'a SimConfig,
+    clock: Clock,
+    events: BinaryHeap<Scheduled>,
+    seq: u64,
+    table: FunctionTable,
+    outstanding: Outstanding,
+    computers: Vec<Computer>,
+    rng: StdRng,
+    // Tasks the load has yet to send, per closed-loop client or in total
+    remaining: Vec<usize>,
+    outcome: SimOutcome,
+}
+
+impl Simulation<'_> {
+    fn schedule(&mut self, at: Duration, event: Event) {
+        self.seq += 1;
+        self.events.push(Scheduled {
+            at,
+            seq: self.seq,
+            event,
+        });
+    }
+
+    fn handle(&mut self, now: Duration, event: Event) {
+        match event {
+            Event::Arrival { client } => self.arrive(now, client),
+            Event::Delivered(mut task) => {
+                task.delivered = now;
+                let computer = &mut self.computers[task.computer];
+                if computer.busy < computer.workers {
+                    self.start(now, task);
+                } else {
+                    computer.queue.push_back(task);
+                }
+            }
+            Event::Finished(mut task) => {
+                let computer = &mut self.computers[task.computer];
+                computer.instances.release(&self.config.function, now);
+                computer.busy -= 1;
+                let hops = computer.hops;
+                if let Some(next) = computer.queue.pop_front() {
+                    self.start(now, next);
+                }
+                let computer = &self.computers[task.computer];
+                let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+                task.feedback = Some(Feedback {
+                    queue_len: computer.queue.len(),
+                    active_workers: computer.busy,
+                    workers: computer.workers,
+                    service_ms: Some(millis(now - task.started)),
+                    queue_ms: Some(millis(task.started - task.delivered)),
+                });
+                let back = now + self.config.network.delay(hops, &mut self.rng);
+                self.schedule(back, Event::Returned(task));
+            }
+            Event::Returned(task) => {
+                let latency = now - task.sent;
+                let port = task.computer as u16 + 1;
+                drop(task.attempt);
+                if let Some(feedback) = &task.feedback {
+                    self.table
+                        .on_feedback(&self.config.function, port, feedback);
+                }
+                self.table
+                    .on_completion(&self.config.function, port, latency);
+                self.outcome.latencies.push(latency.as_secs_f64());
+                if let Some(client) = task.client {
+                    self.next_arrival(now, Some(client));
+                }
+            }
+        }
+    }
+
+    fn arrive(&mut self, now: Duration, client: Option<usize>) {
+        let size = self.config.sizes.sample(&mut self.rng);
+        if let Arrivals::Poisson { rate, .. } = self.config.arrivals {
+            let gap = Exp::new(rate).unwrap().sample(&mut self.rng);
+            self.next_arrival(now + Duration::from_secs_f64(gap), None);
+        }
+
+        // Destinations are numbered from 1 in the order the computers were given
+        let Some(port) = self.table.select(&self.config.function) else {
+            self.outcome.failures += 1;
+            if client.is_some() {
+                self.next_arrival(now, client);
+            }
+            return;
+        };
+        let computer = port as usize - 1;
+        self.outcome.assigned[computer] += 1;
+        // Simulated tasks have no ids
+        let attempt = self.outstanding.start("", &self.config.function, port);
+        let task = SimTask {
+            size,
+            sent: now,
+            computer,
+            client,
+            attempt,
+            delivered: Duration::ZERO,
+            started: Duration::ZERO,
+            feedback: None,
+        };
+        let hops = self.computers[computer].hops;
+        let at = now + self.config.network.delay(hops, &mut self.rng);
+        self.schedule(at, Event::Delivered(task));
+    }
+
+    // Schedule the client's (or the open loop's) next task if it has any left
+    fn next_arrival(&mut self, at: Duration, client: Option<usize>) {
+        let remaining = &mut self.remaining[client.unwrap_or(0)];
+        if *remaining > 0 {
+            *remaining -= 1;
+            self.schedule(at, Event::Arrival { client });
+        }
+    }
+
+    fn start(&mut self, now: Duration, mut task: SimTask) {
+        task.started = now;
+        let computer = &mut self.computers[task.computer];
+        let processing_time = computer.service.sample(task.size, computer.speed);
+        let (start_up, cold) = computer.instances.acquire(&self.config.function, now);
+        if cold {
+            self.outcome.cold_starts += 1;
+        }
+        computer.busy += 1;
+        self.schedule(now + start_up + processing_time, Event::Finished(task));
+    }
+}
+
+// E-computers are numbered from 1 as their dispatchers' ports, so there can
+// be at most this many
+pub const MAX_COMPUTERS: usize = u16::MAX as usize - 1;
+
+// Simulate `config` to the end, or say why it can't be
+pub fn run(config: &SimConfig) -> Result<SimOutcome, String> {
+    if config.computers.len() > MAX_COMPUTERS {
+        return Err(format!(
+            "{} e-computers are too many to simulate, at most {}",
+            config.computers.len(),
+            MAX_COMPUTERS
+        ));
+    }
+    let clock = Clock::virtual_clock();
+    let environment = Environment {
+        clock: clock.clone(),
+        seed: Some(config.seed),
+        outstanding: Outstanding::new(clock.clone()),
+        estimator: config.estimator,
+        bootstrap: config.bootstrap,
+        slos: config.slos.clone(),
+    };
+    let outstanding = environment.outstanding.clone();
+    let mut table = FunctionTable::new(config.algorithm.factory_in(environment));
+    let mut computers = Vec::new();
+    for (i, computer) in config.computers.iter().enumerate() {
+        table.add_computer(
+            Destination::new(i as u16 + 1, computer.hops),
+            computer.hosts.clone(),
+        );
+        computers.push(Computer {
+            speed: computer.speed,
+            hops: computer.hops,
+            workers: computer.workers,
+            busy: 0,
+            queue: VecDeque::new(),
+            // Separate streams so adding a computer doesn't shift the others' draws
+            service: ServiceModel::new(
+                config.service,
+                Some(config.seed.wrapping_add(1 + i as u64)),
+            ),
+            instances: InstancePool::new(config.cold_start),
+        });
+    }
+
+    let remaining = match config.arrivals {
+        Arrivals::Closed {
+            clients,
+            tasks_per_client,
+        } => vec![tasks_per_client; clients],
+        Arrivals::Poisson { tasks, .. } => vec![tasks],
+    };
+    let mut simulation = Simulation {
+        config,
+        clock,
+        events: BinaryHeap::new(),
+        seq: 0,
+        table,
+        outstanding,
+        computers,
+        rng: StdRng::seed_from_u64(config.seed),
+        remaining,
+        outcome: SimOutcome {
+            assigned: vec![0; config.computers.len()],
+            ..SimOutcome::default()
+        },
+    };
+
+    match config.arrivals {
+        Arrivals::Closed { clients, .. } => {
+            for client in 0..clients {
+                simulation.next_arrival(Duration::ZERO, Some(client));
+            }
+        }
+        Arrivals::Poisson { .. } => simulation.next_arrival(Duration::ZERO, None),
+    }
+
+    while let Some(Scheduled { at, event, .. }) = simulation.events.pop() {
+        simulation.clock.set(at);
+        simulation.outcome.elapsed = at;
+        simulation.outcome.events += 1;
+        simulation.handle(at, event);
+    }
+    Ok(simulation.outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(algorithm: Algorithm) -> SimConfig {
+        SimConfig {
+            algorithm,
+            computers: ["100:1", "50:2:2", "200:4"]
+                .iter()
+                .map(|spec| spec.parse().unwrap())
+                .collect(),
+            arrivals: Arrivals::Poisson {
+                rate: 20.0,
+                tasks: 200,
+            },
+            sizes: SizeDistribution::Fixed(5000),
+            function: crate::protocol::DEFAULT_FUNCTION.to_string(),
+            service: ServiceTime::Exponential,
+            network: NetworkModel::default(),
+            cold_start: ColdStartConfig::default(),
+            estimator: Estimator::default(),
+            bootstrap: Bootstrap::default(),
+            slos: Slos::default(),
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn the_same_config_gives_the_same_run() {
+        for algorithm in [
+            Algorithm::RP,
+            Algorithm::PowerOfChoices { choices: 2 },
+            Algorithm::Slo,
+        ] {
+            let config = config(algorithm);
+            let first = run(&config).unwrap();
+            let second = run(&config).unwrap();
+            assert_eq!(first.latencies.len(), 200);
+            assert_eq!(first.latencies, second.latencies);
+            assert_eq!(first.assigned, second.assigned);
+            assert_eq!(first.elapsed, second.elapsed);
+        }
+    }
+
+    #[test]
+    fn computer_specs_reject_out_of_range_fields() {
+        let computer: SimComputer = "100:3:0@resize".parse().unwrap();
+        assert_eq!(
+            (computer.speed, computer.hops, computer.workers),
+            (100, 3, 1)
+        );
+        assert!("100:4294967296".parse::<SimComputer>().is_err());
+        assert!("100:-1".parse::<SimComputer>().is_err());
+        assert!(":1".parse::<SimComputer>().is_err());
+    }
+
+    #[test]
+    fn too_many_computers_are_an_error() {
+        let mut config = config(Algorithm::RR);
+        let computer = config.computers[0].clone();
+        config.computers = vec![computer; MAX_COMPUTERS + 1];
+        assert!(run(&config).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+// Latency objectives per function, e.g. 95% of `resize` tasks answered within
+// 300 ms, and the count of tasks that missed them.
+use crate::protocol::{SloReport, Status};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// At least `percentile`% of tasks answered within `target`. Given as
+// `pP:TARGET_MS`, e.g. `p95:300`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slo {
+    pub percentile: f64,
+    pub target: Duration,
+}
+
+impl Slo {
+    // Whether a task that ended with `status` after `latency` counts towards
+    // the objective; a task that wasn't answered never does
+    pub fn is_met(&self, status: Status, latency: Duration) -> bool {
+        status == Status::Ok && latency <= self.target
+    }
+
+    // Share of tasks allowed to miss the target
+    pub fn budget(&self) -> f64 {
+        1.0 - self.percentile / 100.0
+    }
+}
+
+impl FromStr for Slo {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid SLO {:?}. Expected pP:TARGET_MS, e.g. p95:300.",
+                spec
+            )
+        };
+        let (percentile, target) = spec
+            .strip_prefix('p')
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or_else(invalid)?;
+        let percentile: f64 = percentile.parse().map_err(|_| invalid())?;
+        if !(percentile > 0.0 && percentile < 100.0) {
+            return Err(format!(
+                "Percentile must be between 0 and 100 in {:?}",
+                spec
+            ));
+        }
+        let target: f64 = target.parse().map_err(|_| invalid())?;
+        if !(target > 0.0 && target.is_finite()) {
+            return Err(format!("Target must be positive in {:?}", spec));
+        }
+        let target = Duration::try_from_secs_f64(target / 1000.0)
+            .map_err(|_| format!("Target out of range in {:?}", spec))?;
+        Ok(Slo { percentile, target })
+    }
+}
+
+impl fmt::Display for Slo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p{}:{}",
+            self.percentile,
+            self.target.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+// The objective of each function: one for all functions, optionally replaced
+// for some of them. Given as `[SLO][,FUNCTION=SLO...]`, e.g. `p95:300` or
+// `p95:300,resize=p99:1000`. Functions without one have no objective.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Slos {
+    pub default: Option<Slo>,
+    pub by_function: BTreeMap<String, Slo>,
+}
+
+impl Slos {
+    pub fn get(&self, function: &str) -> Option<Slo> {
+        self.by_function.get(function).copied().or(self.default)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.by_function.is_empty()
+    }
+}
+
+impl FromStr for Slos {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut slos = Slos::default();
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((function, slo)) => {
+                    slos.by_function.insert(function.to_string(), slo.parse()?);
+                }
+                None => slos.default = Some(part.parse()?),
+            }
+        }
+        Ok(slos)
+    }
+}
+
+impl fmt::Display for Slos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self.default.iter().map(Slo::to_string).collect();
+        parts.extend(
+            self.by_function
+                .iter()
+                .map(|(function, slo)| format!("{}={}", function, slo)),
+        );
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+// Tasks of one function with an objective, and how many missed it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SloStats {
+    pub tasks: u64,
+    pub violations: u64,
+}
+
+impl SloStats {
+    pub fn record(&mut self, met: bool) {
+        self.tasks += 1;
+        if !met {
+            self.violations += 1;
+        }
+    }
+
+    pub fn violation_rate(&self) -> f64 {
+        if self.tasks == 0 {
+            0.0
+        } else {
+            self.violations as f64 / self.tasks as f64
+        }
+    }
+
+    // Whether no more tasks missed the target than the objective allows
+    pub fn is_met(&self, slo: &Slo) -> bool {
+        self.violation_rate() <= slo.budget()
+    }
+}
+
+// What the e-router counts for every function with an objective
+#[derive(Debug, Default)]
+pub struct SloMetrics {
+    slos: Slos,
+    stats: BTreeMap<String, SloStats>,
+}
+
+impl SloMetrics {
+    pub fn new(slos: Slos) -> Self {
+        SloMetrics {
+            slos,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    // Count a task of `function` that ended with `status` after `latency`,
+    // if the function has an objective
+    pub fn record(&mut self, function: &str, status: Status, latency: Duration) {
+        if let Some(slo) = self.slos.get(function) {
+            self.stats
+                .entry(function.to_string())
+                .or_default()
+                .record(slo.is_met(status, latency));
+        }
+    }
+
+    pub fn report(&self) -> Vec<SloReport> {
+        self.stats
+            .iter()
+            .filter_map(|(function, stats)| {
+                let slo = self.slos.get(function)?;
+                Some(SloReport {
+                    function: function.clone(),
+                    percentile: slo.percentile,
+                    target_ms: slo.target.as_secs_f64() * 1000.0,
+                    tasks: stats.tasks,
+                    violations: stats.violations,
+                    met: stats.is_met(&slo),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions_override_the_default() {
+        let slos: Slos = "p95:300,resize=p99:1000".parse().unwrap();
+        assert_eq!(slos.to_string(), "p95:300,resize=p99:1000");
+        assert_eq!(slos.get("resize").unwrap().target, Duration::from_secs(1));
+        assert_eq!(slos.get("other").unwrap().percentile, 95.0);
+        assert_eq!(
+            "resize=p99:1000".parse::<Slos>().unwrap().get("other"),
+            None
+        );
+        assert!("95:300".parse::<Slos>().is_err());
+        assert!("p100:300".parse::<Slos>().is_err());
+        for spec in ["p95:0", "p95:-1", "p95:inf", "p95:NaN", "p95:1e300"] {
+            assert!(spec.parse::<Slos>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn failures_count_as_violations() {
+        let mut metrics = SloMetrics::new("p50:100".parse().unwrap());
+        let ms = Duration::from_millis;
+        metrics.record("f", Status::Ok, ms(50));
+        metrics.record("f", Status::Ok, ms(150));
+        metrics.record("f", Status::Timeout, ms(10));
+        let report = &metrics.report()[0];
+        assert_eq!((report.tasks, report.violations), (3, 2));
+        assert!(!report.met);
+    }
+}
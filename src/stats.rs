@@ -0,0 +1,243 @@
+// Latency statistics for comparing experiment runs, replacing the copy-paste
+// into `scripts/per95.py`.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use statrs::statistics::Statistics;
+
+pub const DEFAULT_RESAMPLES: usize = 1000;
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+
+// Extract one latency per line. Accepts the `0.1234` and `0.1234,` lines of
+// the latency logs (and of `e_client --output -`) as well as the live output
+// of `scripts/manager-95.py`, `Processed task with time: 0.1234 seconds`.
+// Other lines are skipped, so nothing else those print may be a bare number.
+pub fn parse_latencies(text: &str) -> Vec<f64> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim().trim_end_matches(',').trim_end();
+            line.parse::<f64>().ok().or_else(|| {
+                let (_, rest) = line.split_once("time:")?;
+                rest.split_whitespace().next()?.parse().ok()
+            })
+        })
+        .filter(|latency| latency.is_finite())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Interval {
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub name: String,
+    pub count: usize,
+    pub mean: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+    // Bootstrap confidence intervals
+    pub mean_ci: Interval,
+    pub p95_ci: Interval,
+}
+
+fn sorted(samples: &[f64]) -> Vec<f64> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted
+}
+
+// Quantile of sorted samples, interpolated linearly between the closest ranks
+// (R-7), as numpy's `percentile` computes it for `scripts/per95.py`
+fn quantile(sorted: &[f64], tau: f64) -> f64 {
+    let rank = (sorted.len() - 1) as f64 * tau.clamp(0.0, 1.0);
+    let below = rank.floor() as usize;
+    let above = (below + 1).min(sorted.len() - 1);
+    sorted[below] + (rank - below as f64) * (sorted[above] - sorted[below])
+}
+
+// Percentile bootstrap: resample with replacement, recompute the statistic and
+// take the central `confidence` share of the results
+pub fn bootstrap<F: Fn(&[f64]) -> f64>(
+    samples: &[f64],
+    statistic: F,
+    resamples: usize,
+    confidence: f64,
+    rng: &mut StdRng,
+) -> Interval {
+    let estimates: Vec<f64> = (0..resamples)
+        .map(|_| {
+            let resample: Vec<f64> = (0..samples.len())
+                .map(|_| samples[rng.gen_range(0..samples.len())])
+                .collect();
+            statistic(&resample)
+        })
+        .collect();
+    let tail = (1.0 - confidence) / 2.0;
+    let estimates = sorted(&estimates);
+    Interval {
+        low: quantile(&estimates, tail),
+        high: quantile(&estimates, 1.0 - tail),
+    }
+}
+
+pub fn summarize(name: &str, samples: &[f64], resamples: usize, seed: u64) -> Option<Summary> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let data = sorted(samples);
+    Some(Summary {
+        name: name.to_string(),
+        count: samples.len(),
+        mean: samples.mean(),
+        stddev: if samples.len() > 1 {
+            samples.std_dev()
+        } else {
+            0.0
+        },
+        min: samples.min(),
+        p50: quantile(&data, 0.5),
+        p90: quantile(&data, 0.9),
+        p95: quantile(&data, 0.95),
+        p99: quantile(&data, 0.99),
+        p999: quantile(&data, 0.999),
+        max: samples.max(),
+        mean_ci: bootstrap(
+            samples,
+            |s| s.mean(),
+            resamples,
+            DEFAULT_CONFIDENCE,
+            &mut rng,
+        ),
+        p95_ci: bootstrap(
+            samples,
+            |s| quantile(&sorted(s), 0.95),
+            resamples,
+            DEFAULT_CONFIDENCE,
+            &mut rng,
+        ),
+    })
+}
+
+pub const CSV_HEADER: &str = "name,count,mean,stddev,min,p50,p90,p95,p99,p99.9,max,mean_ci_low,mean_ci_high,p95_ci_low,p95_ci_high";
+
+impl Summary {
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}",
+            self.name,
+            self.count,
+            self.mean,
+            self.stddev,
+            self.min,
+            self.p50,
+            self.p90,
+            self.p95,
+            self.p99,
+            self.p999,
+            self.max,
+            self.mean_ci.low,
+            self.mean_ci.high,
+            self.p95_ci.low,
+            self.p95_ci.high
+        )
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{}: {} samples\n  mean   {:.4} s (95% CI {:.4} - {:.4}), stddev {:.4}\n  p50 {:.4}  p90 {:.4}  p95 {:.4} (95% CI {:.4} - {:.4})  p99 {:.4}  p99.9 {:.4}\n  min {:.4}  max {:.4}",
+            self.name,
+            self.count,
+            self.mean,
+            self.mean_ci.low,
+            self.mean_ci.high,
+            self.stddev,
+            self.p50,
+            self.p90,
+            self.p95,
+            self.p95_ci.low,
+            self.p95_ci.high,
+            self.p99,
+            self.p999,
+            self.min,
+            self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_logs_and_manager_output() {
+        let log = include_str!("../scripts/exp-2-per-95/process-times-1.log");
+        assert_eq!(parse_latencies(log).len(), log.lines().count());
+        assert_eq!(parse_latencies("0.4734,\n0.2317,\n"), vec![0.4734, 0.2317]);
+
+        let output = "\
+Processed task with time: 0.4734 seconds
+Processed task with time: 0.2317 seconds
+Traceback (most recent call last):
+ConnectionRefusedError: [Errno 111] Connection refused
+Processed task with time: 1.0250 seconds
+";
+        assert_eq!(parse_latencies(output), vec![0.4734, 0.2317, 1.025]);
+    }
+
+    #[test]
+    fn quantiles_interpolate_like_numpy() {
+        let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+        let summary = summarize("run", &samples, 200, 7).unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!((summary.min, summary.max), (1.0, 100.0));
+        assert!((summary.mean - 50.5).abs() < 1e-9);
+        assert!((summary.stddev - 29.011491975882016).abs() < 1e-9);
+        // numpy.percentile(range(1, 101), [50, 90, 95, 99, 99.9])
+        let expected = [50.5, 90.1, 95.05, 99.01, 99.901];
+        let quantiles = [
+            summary.p50,
+            summary.p90,
+            summary.p95,
+            summary.p99,
+            summary.p999,
+        ];
+        for (quantile, expected) in quantiles.into_iter().zip(expected) {
+            assert!(
+                (quantile - expected).abs() < 1e-9,
+                "{} != {}",
+                quantile,
+                expected
+            );
+        }
+        assert_eq!(summarize("single", &[0.25], 10, 0).unwrap().p95, 0.25);
+        assert!(summarize("empty", &[], 10, 0).is_none());
+    }
+
+    #[test]
+    fn bootstrap_intervals_bracket_the_estimate() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples: Vec<f64> = (0..500).map(|_| rng.gen_range(0.1..0.5)).collect();
+        let summary = summarize("run", &samples, 500, 3).unwrap();
+        for (estimate, interval) in [
+            (summary.mean, summary.mean_ci),
+            (summary.p95, summary.p95_ci),
+        ] {
+            assert!(interval.low < estimate && estimate < interval.high);
+            assert!(interval.high - interval.low < 0.05);
+        }
+        // The same seed resamples the same way
+        let again = summarize("run", &samples, 500, 3).unwrap();
+        assert_eq!(again.p95_ci.low, summary.p95_ci.low);
+        assert_eq!(again.mean_ci.high, summary.mean_ci.high);
+    }
+}
@@ -0,0 +1,112 @@
+// How an e-computer spends a task's service time. `Sleep` only waits, so any
+// number of e-computers can share a host without noticing each other. `Cpu`
+// burns a calibrated number of hashing operations instead, so e-computers on
+// the same host (or under a CPU cap) contend for the processor like real
+// edge nodes and take longer than their nominal service time when they do.
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::hint::black_box;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Operations hashed between clock checks during calibration
+const CALIBRATION_CHUNK: u64 = 4096;
+
+pub const DEFAULT_CALIBRATION_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkMode {
+    Sleep,
+    Cpu,
+}
+
+impl FromStr for WorkMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "sleep" => Ok(WorkMode::Sleep),
+            "cpu" => Ok(WorkMode::Cpu),
+            _ => Err(format!(
+                "Invalid work mode {:?}. Choose sleep or cpu.",
+                mode
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WorkMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkMode::Sleep => write!(f, "sleep"),
+            WorkMode::Cpu => write!(f, "cpu"),
+        }
+    }
+}
+
+// Hash `ops` times, feeding each digest into the next
+fn spin(ops: u64, seed: u64) -> u64 {
+    let mut state = seed;
+    for i in 0..ops {
+        let mut hasher = DefaultHasher::new();
+        (state, i).hash(&mut hasher);
+        state = hasher.finish();
+    }
+    black_box(state)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Worker {
+    mode: WorkMode,
+    ops_per_sec: f64,
+}
+
+impl Worker {
+    pub fn sleeping() -> Self {
+        Worker {
+            mode: WorkMode::Sleep,
+            ops_per_sec: 0.0,
+        }
+    }
+
+    // A CPU worker with a known rate, e.g. to reuse the calibration of an earlier run
+    pub fn cpu(ops_per_sec: f64) -> Self {
+        Worker {
+            mode: WorkMode::Cpu,
+            ops_per_sec,
+        }
+    }
+
+    // Measure how many hashing operations per second one core does right now
+    pub fn calibrate(window: Duration) -> Self {
+        let start = Instant::now();
+        let mut ops = 0;
+        let mut seed = 0;
+        while start.elapsed() < window {
+            seed = spin(CALIBRATION_CHUNK, seed);
+            ops += CALIBRATION_CHUNK;
+        }
+        Worker::cpu(ops as f64 / start.elapsed().as_secs_f64())
+    }
+
+    pub fn mode(&self) -> WorkMode {
+        self.mode
+    }
+
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops_per_sec
+    }
+
+    // Spend `service_time` of work: sleeping, or burning the amount of CPU an
+    // uncontended core needs for that long
+    pub fn run(&self, service_time: Duration) {
+        match self.mode {
+            WorkMode::Sleep => thread::sleep(service_time),
+            WorkMode::Cpu => {
+                spin((service_time.as_secs_f64() * self.ops_per_sec) as u64, 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Task size distributions for the load generator
+use rand::distributions::Distribution;
+use rand::Rng;
+use statrs::distribution::Exp;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeDistribution {
+    Fixed(u64),
+    // Uniform over [min, max]
+    Uniform(u64, u64),
+    // Exponential with the given mean
+    Exponential(f64),
+}
+
+impl SizeDistribution {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
+        match *self {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform(min, max) => rng.gen_range(min..=max),
+            SizeDistribution::Exponential(mean) => {
+                Exp::new(1.0 / mean).unwrap().sample(rng).round() as u64
+            }
+        }
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    // fixed:SIZE | uniform:MIN:MAX | exp:MEAN, or just SIZE
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let invalid = || format!("Invalid task size {:?}", spec);
+        let param = |i: usize| -> Result<u64, String> {
+            parts
+                .get(i)
+                .ok_or_else(invalid)?
+                .parse()
+                .map_err(|_| invalid())
+        };
+        match parts[0] {
+            "fixed" => Ok(SizeDistribution::Fixed(param(1)?)),
+            "uniform" => {
+                let (min, max) = (param(1)?, param(2)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(SizeDistribution::Uniform(min, max))
+            }
+            "exp" => match param(1)? {
+                0 => Err(invalid()),
+                mean => Ok(SizeDistribution::Exponential(mean as f64)),
+            },
+            size => size.parse().map(SizeDistribution::Fixed).map_err(|_| {
+                format!(
+                    "Invalid task size {:?}. Use SIZE, fixed:SIZE, uniform:MIN:MAX or exp:MEAN.",
+                    spec
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "fixed:{}", size),
+            SizeDistribution::Uniform(min, max) => write!(f, "uniform:{}:{}", min, max),
+            SizeDistribution::Exponential(mean) => write!(f, "exp:{}", mean),
+        }
+    }
+}